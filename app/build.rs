@@ -4,10 +4,19 @@ include!("src/deps/prost_build/mod.rs");
 #[cfg(feature = "use_dependencies")]
 extern crate prost_build;
 
+/// Lets the API serve a decoded feed as JSON.
+const SERIALIZE: &str = "#[derive(serde::Serialize)]";
+
 fn main() {
     #[cfg(not(feature = "use_dependencies"))]
-    compile_protos(&["src/proto/gtfs-realtime.proto"], &["src/"]).unwrap();
+    Config::new()
+        .type_attribute(".", SERIALIZE)
+        .compile_protos(&["src/proto/gtfs-realtime.proto"], &["src/"])
+        .unwrap();
 
     #[cfg(feature = "use_dependencies")]
-    prost_build::compile_protos(&["src/proto/gtfs-realtime.proto"], &["src/"]).unwrap();
+    prost_build::Config::new()
+        .type_attribute(".", SERIALIZE)
+        .compile_protos(&["src/proto/gtfs-realtime.proto"], &["src/"])
+        .unwrap();
 }
@@ -1,28 +1,43 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     middleware::from_fn,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
+use tracing::{error, warn};
+use tulsa::AdmissionError;
 
 use crate::{
+    cache::Latest,
     middleware::log_request,
-    models::{CreateFeed, Feed, Status},
-    scheduler_interface::ToScheduler,
+    models::{
+        realtime::{self, AlertQuery, Arrival, ArrivalQuery, TripQuery, VehicleQuery},
+        CreateFeed, Feed, FeedStatus, FeedSummary, InvalidFeed, LatestFeed, Run, Status,
+    },
+    scheduler_interface::{AppSendError, ToScheduler},
+    store::{FeedStore, MemoryStore, StoreError},
 };
 
+/// The request header which makes a retried `POST /feed` return the feed it created before.
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// The media type of a GTFS-Realtime message, which a client can ask for with `Accept`.
+const PROTOBUF: &str = "application/x-protobuf";
+
+/// The media type of the vehicles of a feed.
+const GEOJSON: &str = "application/geo+json";
+
 struct AppState<T>
 where
     T: ToScheduler + Send + Sync + 'static,
 {
-    next_feed_id: Arc<RwLock<usize>>,
-    db: Arc<RwLock<HashMap<usize, Feed>>>,
+    store: Arc<dyn FeedStore>,
     scheduler_interface: Arc<T>,
 }
 
@@ -32,20 +47,80 @@ where
 {
     fn clone(&self) -> Self {
         Self {
-            next_feed_id: self.next_feed_id.clone(),
-            db: self.db.clone(),
+            store: self.store.clone(),
             scheduler_interface: self.scheduler_interface.clone(),
         }
     }
 }
 
+impl From<AppSendError> for StatusCode {
+    fn from(e: AppSendError) -> Self {
+        match e {
+            AppSendError::Rejected(AdmissionError::FrequencyTooLow(_)) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            AppSendError::Rejected(
+                AdmissionError::TooManyTasks(_) | AdmissionError::QueueFull(_),
+            ) => StatusCode::SERVICE_UNAVAILABLE,
+            AppSendError::Rejected(AdmissionError::Disconnected) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AppSendError::Bounds(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl From<InvalidFeed> for StatusCode {
+    fn from(_: InvalidFeed) -> Self {
+        StatusCode::UNPROCESSABLE_ENTITY
+    }
+}
+
+impl From<StoreError> for StatusCode {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Rejected(e) => StatusCode::from(e),
+            e => {
+                error!("{}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// The API with its feeds kept in memory.
 pub fn app<T>(scheduler_interface: Arc<T>) -> Router
 where
     T: ToScheduler + Send + Sync + 'static,
 {
+    app_with_store(scheduler_interface, Arc::new(MemoryStore::new()))
+}
+
+/// The API with its feeds kept in `store`. Every stored feed is scheduled right away.
+pub fn app_with_store<T>(scheduler_interface: Arc<T>, store: Arc<dyn FeedStore>) -> Router
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    // The feeds of a snapshot keep their ids, and pick up their timing once they are scheduled.
+    for feed in scheduler_interface.restored() {
+        if let Err(e) = store.insert(feed) {
+            error!("Cannot store a restored feed: {}", e);
+        }
+    }
+    match store.list() {
+        Ok(feeds) => {
+            for feed in feeds {
+                let id = feed.id;
+                if let Err(e) = scheduler_interface.create(feed) {
+                    warn!("Cannot schedule stored feed {}: {}", id, e);
+                }
+            }
+        }
+        Err(e) => error!("Cannot load stored feeds: {}", e),
+    }
+
     let state = AppState {
-        next_feed_id: Arc::new(RwLock::new(1)),
-        db: Arc::new(RwLock::new(HashMap::new())),
+        store,
         scheduler_interface,
     };
 
@@ -55,7 +130,20 @@ where
             "/feed/:key",
             get(get_handler).put(put_handler).delete(delete_handler),
         )
+        .route("/feed/:key/history", get(history_handler))
+        .route("/feed/:key/status", get(feed_status_handler))
+        .route("/feed/:key/fetch", post(fetch_handler))
+        .route("/feed/:key/latest", get(latest_handler))
+        .route("/feed/:key/trip_updates", get(trip_updates_handler))
+        .route("/feed/:key/trip_updates/:trip_id", get(trip_update_handler))
+        .route("/feed/:key/vehicles", get(vehicles_handler))
+        .route("/feed/:key/alerts", get(alerts_handler))
         .route("/feed", post(post_handler).get(list_handler))
+        .route("/stops/:stop_id/arrivals", get(arrivals_handler))
+        // Like the rest of the API, these are not authenticated, so they must only be reachable
+        // from inside the deployment.
+        .route("/metrics", get(metrics_handler))
+        .route("/snapshot", get(snapshot_handler))
         .layer(from_fn(log_request))
         .with_state(state)
 }
@@ -66,42 +154,57 @@ async fn status_handler() -> impl IntoResponse {
 
 async fn post_handler<T>(
     state: State<AppState<T>>,
-    Json(CreateFeed {
-        name,
-        url,
-        frequency,
-        headers,
-    }): Json<CreateFeed>,
+    headers: HeaderMap,
+    Json(input): Json<CreateFeed>,
 ) -> Result<impl IntoResponse, StatusCode>
 where
     T: ToScheduler + Send + Sync + 'static,
 {
-    let id = *(state
-        .next_feed_id
-        .read()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
-    let feed = Feed {
-        id,
+    input.validate()?;
+    let CreateFeed {
         name,
         url,
         frequency,
-        headers,
+        headers: feed_headers,
+        staleness,
+        archive,
+    } = input;
+
+    // A retried request with the same key gets the feed the first one created.
+    let key = match headers.get(IDEMPOTENCY_KEY) {
+        Some(key) => Some(key.to_str().map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
     };
 
-    *(state
-        .next_feed_id
-        .write()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?) += 1;
-    state
-        .db
-        .write()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .insert(id, feed.clone());
-
-    state
-        .scheduler_interface
-        .create(feed.clone())
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Schedule the feed first so that a rejected feed is never stored, and stop it again if it
+    // cannot be stored.
+    let mut scheduled = None;
+    let created = state.store.create(key, &mut |id| {
+        let feed = Feed {
+            id,
+            name: name.clone(),
+            url: url.clone(),
+            frequency,
+            headers: feed_headers.clone(),
+            staleness,
+            archive,
+        };
+        state.scheduler_interface.create(feed.clone())?;
+        scheduled = Some(feed.clone());
+        Ok(feed)
+    });
+    let feed = match created {
+        Ok(feed) => feed,
+        Err(e) => {
+            if let Some(feed) = scheduled {
+                let id = feed.id;
+                if let Err(e) = state.scheduler_interface.delete(feed) {
+                    error!("Cannot stop feed {} which was not stored: {}", id, e);
+                }
+            }
+            return Err(e.into());
+        }
+    };
 
     Ok((StatusCode::CREATED, Json(feed)))
 }
@@ -113,13 +216,7 @@ async fn get_handler<T>(
 where
     T: ToScheduler + Send + Sync + 'static,
 {
-    let feed = state
-        .db
-        .read()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .get(&id)
-        .cloned()
-        .ok_or(StatusCode::NOT_FOUND)?;
+    let feed = state.store.get(id)?.ok_or(StatusCode::NOT_FOUND)?;
 
     Ok(Json(feed))
 }
@@ -127,25 +224,21 @@ where
 async fn put_handler<T>(
     Path(id): Path<usize>,
     state: State<AppState<T>>,
-    Json(CreateFeed {
-        name,
-        url,
-        frequency,
-        headers,
-    }): Json<CreateFeed>,
+    Json(input): Json<CreateFeed>,
 ) -> impl IntoResponse
 where
     T: ToScheduler + Send + Sync + 'static,
 {
-    if state
-        .db
-        .read()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .get(&id)
-        .is_none()
-    {
-        return Err(StatusCode::NOT_FOUND);
-    }
+    let old = state.store.get(id)?.ok_or(StatusCode::NOT_FOUND)?;
+    input.validate()?;
+    let CreateFeed {
+        name,
+        url,
+        frequency,
+        headers,
+        staleness,
+        archive,
+    } = input;
 
     let feed = Feed {
         id,
@@ -153,17 +246,21 @@ where
         url,
         frequency,
         headers,
+        staleness,
+        archive,
     };
 
-    state
-        .db
-        .write()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .insert(id, feed.clone());
     state
         .scheduler_interface
         .update(feed.clone())
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(StatusCode::from)?;
+    // The scheduler goes back to the stored feed if the new one cannot be stored.
+    if let Err(e) = state.store.insert(feed.clone()) {
+        if let Err(e) = state.scheduler_interface.update(old) {
+            error!("Cannot restore feed {} which was not stored: {}", id, e);
+        }
+        return Err(StatusCode::from(e));
+    }
 
     Ok(Json(feed))
 }
@@ -175,37 +272,261 @@ async fn delete_handler<T>(
 where
     T: ToScheduler + Send + Sync + 'static,
 {
-    let feed = state
-        .db
-        .read()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .get(&id)
-        .cloned()
+    let feed = state.store.get(id)?.ok_or(StatusCode::NOT_FOUND)?;
+
+    // Stop the feed first so that a feed which keeps running is never removed from the store,
+    // and schedule it again if it cannot be removed.
+    state
+        .scheduler_interface
+        .delete(feed.clone())
+        .map_err(StatusCode::from)?;
+    if let Err(e) = state.store.remove(id) {
+        if let Err(e) = state.scheduler_interface.create(feed) {
+            error!("Cannot restart feed {} which was not removed: {}", id, e);
+        }
+        return Err(StatusCode::from(e));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn history_handler<T>(
+    Path(id): Path<usize>,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    if state.store.get(id)?.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let runs: Vec<Run> = state
+        .scheduler_interface
+        .history(id)
+        .into_iter()
+        .map(Run::from)
+        .collect();
+    Ok(Json(runs))
+}
+
+/// How the fetches of a feed have gone.
+async fn feed_status_handler<T>(
+    Path(id): Path<usize>,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    if state.store.get(id)?.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let health = state.scheduler_interface.health(id);
+    Ok(Json(FeedStatus::new(id, health)))
+}
+
+/// The most recent message of a feed as JSON, or as it was fetched if the client accepts protobuf.
+async fn latest_handler<T>(
+    Path(id): Path<usize>,
+    headers: HeaderMap,
+    state: State<AppState<T>>,
+) -> Result<Response, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    let latest = latest(&state, id)?;
+
+    let accepts_protobuf = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(PROTOBUF));
+    if accepts_protobuf {
+        return Ok(([(header::CONTENT_TYPE, PROTOBUF)], latest.raw.clone()).into_response());
+    }
+    Ok(Json(LatestFeed::from(latest.as_ref())).into_response())
+}
+
+/// The trip updates of the most recent message of a feed which match the query.
+async fn trip_updates_handler<T>(
+    Path(id): Path<usize>,
+    Query(query): Query<TripQuery>,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    let latest = latest(&state, id)?;
+
+    Ok(Json(realtime::trip_updates(&latest.message, &query)))
+}
+
+async fn trip_update_handler<T>(
+    Path((id, trip_id)): Path<(usize, String)>,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    let latest = latest(&state, id)?;
+
+    let query = TripQuery {
+        trip_id: Some(trip_id),
+        ..Default::default()
+    };
+    let trip_update = realtime::trip_updates(&latest.message, &query)
+        .into_iter()
+        .next()
         .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(trip_update))
+}
+
+/// The vehicles of the most recent message of a feed as a GeoJSON `FeatureCollection`.
+async fn vehicles_handler<T>(
+    Path(id): Path<usize>,
+    Query(query): Query<VehicleQuery>,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    let latest = latest(&state, id)?;
+
+    let vehicles = realtime::vehicles(&latest.message, &query);
+    Ok(([(header::CONTENT_TYPE, GEOJSON)], Json(vehicles)))
+}
+
+/// The alerts of the most recent message of a feed, with their text in the language asked for by
+/// `lang` or `Accept-Language`.
+async fn alerts_handler<T>(
+    Path(id): Path<usize>,
+    Query(query): Query<AlertQuery>,
+    headers: HeaderMap,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    let latest = latest(&state, id)?;
+
+    let accept_language = match &query.lang {
+        Some(lang) => Some(lang.as_str()),
+        None => headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|accept| accept.to_str().ok()),
+    };
+    let languages = realtime::accepted_languages(accept_language.unwrap_or_default());
+    Ok(Json(realtime::alerts(
+        &latest.message,
+        &query,
+        &languages,
+        unix_now(),
+    )))
+}
+
+/// The upcoming arrivals at a stop across the most recent message of every feed, soonest first.
+async fn arrivals_handler<T>(
+    Path(stop_id): Path<String>,
+    Query(query): Query<ArrivalQuery>,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    let after = query.after.unwrap_or_else(|| unix_now() as i64);
+
+    let mut arrivals: Vec<Arrival> = Vec::new();
+    for feed in state.store.list()? {
+        if let Some(latest) = state.scheduler_interface.latest(feed.id) {
+            arrivals.extend(realtime::arrivals(
+                feed.id,
+                &latest.message,
+                &stop_id,
+                after,
+            ));
+        }
+    }
+    arrivals.sort_by_key(|arrival| arrival.time);
+    if let Some(limit) = query.limit {
+        arrivals.truncate(limit);
+    }
+    Ok(Json(arrivals))
+}
 
+/// The most recent message of a stored feed, or 404 if there is no such feed or nothing was
+/// fetched for it yet.
+fn latest<T>(state: &AppState<T>, id: usize) -> Result<Arc<Latest>, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    if state.store.get(id)?.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
     state
-        .db
-        .write()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .remove(&feed.id);
+        .scheduler_interface
+        .latest(id)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Seconds since the Unix epoch.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The metrics of the scheduler, in the Prometheus text format.
+async fn metrics_handler<T>(state: State<AppState<T>>) -> impl IntoResponse
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.scheduler_interface.metrics(),
+    )
+}
+
+/// The schedule of every feed, for the process which takes over in a blue-green deploy. It has
+/// the headers of every feed, API keys included.
+async fn snapshot_handler<T>(state: State<AppState<T>>) -> impl IntoResponse
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    Json(state.scheduler_interface.snapshot())
+}
+
+async fn fetch_handler<T>(
+    Path(id): Path<usize>,
+    state: State<AppState<T>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    T: ToScheduler + Send + Sync + 'static,
+{
+    if state.store.get(id)?.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
     state
         .scheduler_interface
-        .delete(feed)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .trigger(id)
+        .map_err(StatusCode::from)?;
 
-    Ok(StatusCode::NO_CONTENT)
+    Ok(StatusCode::ACCEPTED)
 }
 
 async fn list_handler<T>(state: State<AppState<T>>) -> Result<impl IntoResponse, StatusCode>
 where
     T: ToScheduler + Send + Sync + 'static,
 {
-    let feeds: Vec<Feed> = state
-        .db
-        .read()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .values()
-        .cloned()
+    let feeds: Vec<FeedSummary> = state
+        .store
+        .list()?
+        .into_iter()
+        .map(|feed| {
+            let health = state.scheduler_interface.health(feed.id);
+            FeedSummary::new(feed, health)
+        })
         .collect();
     Ok(Json(feeds))
 }
@@ -216,17 +537,22 @@ mod api_tests {
         body::Body,
         http::{self, Request, StatusCode},
     };
-    use std::{
-        net::SocketAddr,
-        sync::{mpsc::SendError, Mutex},
-    };
+    use std::{collections::HashMap, net::SocketAddr, sync::Mutex, time::Duration};
     use tokio::net::TcpListener;
     use tower::ServiceExt; // for `oneshot`
 
     #[cfg(not(feature = "use_dependencies"))]
     use crate::deps::mime;
-    use crate::scheduler_interface::{SchedulerInterface, TaskSend};
-    use tulsa::{AsyncTask, Task};
+    use crate::{
+        cache::FeedCache,
+        fetcher::{transit::FeedMessage, FetchError, FetchErrorKind, Summary},
+        health::FeedHealth,
+        models::Staleness,
+        scheduler_interface::{SchedulerInterface, TaskSend},
+        store::FileStore,
+    };
+    use prost::Message;
+    use tulsa::{channel, AsyncTask, Limits, Operation, SchedulerHandle, Task};
 
     use super::*;
 
@@ -246,7 +572,7 @@ mod api_tests {
     where
         T: Task,
     {
-        fn send(&self, task: T) -> Result<(), SendError<T>> {
+        fn send(&self, task: T) -> Result<(), AdmissionError> {
             self.tasks.lock().unwrap().push(task);
             Ok(())
         }
@@ -273,7 +599,10 @@ mod api_tests {
     #[tokio::test]
     async fn status() {
         let sender = MockSender::new();
-        let interface = Arc::new(SchedulerInterface::new(sender.clone()));
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
         let response = app(interface)
             .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
             .await
@@ -291,7 +620,10 @@ mod api_tests {
     #[tokio::test]
     async fn invalid() {
         let sender = MockSender::new();
-        let interface = Arc::new(SchedulerInterface::new(sender.clone()));
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
         let response = app(interface)
             .oneshot(
                 Request::builder()
@@ -310,7 +642,10 @@ mod api_tests {
         assert_eq!(&body[..], b"Invalid URL: Cannot parse `\"abc\"` to a `u64`");
 
         let sender = MockSender::new();
-        let interface = Arc::new(SchedulerInterface::new(sender.clone()));
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
         let response = app(interface)
             .oneshot(
                 Request::builder()
@@ -337,9 +672,14 @@ mod api_tests {
             url: "http".to_string(),
             frequency: 10,
             headers,
+            staleness: None,
+            archive: None,
         };
         let sender = MockSender::new();
-        let interface = Arc::new(SchedulerInterface::new(sender.clone()));
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
         let response = app(interface)
             .oneshot(
                 Request::builder()
@@ -355,7 +695,10 @@ mod api_tests {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
         let sender = MockSender::new();
-        let interface = Arc::new(SchedulerInterface::new(sender.clone()));
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
         let response = app(interface)
             .oneshot(
                 Request::builder()
@@ -379,10 +722,15 @@ mod api_tests {
             url: "http".to_string(),
             frequency: 10,
             headers,
+            staleness: None,
+            archive: None,
         };
         let sender = MockSender::new();
         assert_eq!(sender.count(), 0);
-        let interface = Arc::new(SchedulerInterface::new(sender.clone()));
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
         let response = app(interface)
             .oneshot(
                 Request::builder()
@@ -410,6 +758,1292 @@ mod api_tests {
         assert_eq!(f.headers["auth"], "key");
     }
 
+    #[tokio::test]
+    async fn rejected_post() {
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let limits = Limits {
+            min_frequency: Duration::from_secs(30),
+            ..Limits::default()
+        };
+        let (sender, _receiver) = channel::<AsyncTask>(limits);
+        let interface = Arc::new(SchedulerInterface::new(sender, SchedulerHandle::default()));
+        let router = app(interface);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/feed")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(input))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = router
+            .oneshot(Request::builder().uri("/feed").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"[]");
+    }
+
+    #[tokio::test]
+    async fn invalid_frequency() {
+        let feed = |frequency: u64| CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let router = app(interface);
+
+        // Neither frequency reaches the scheduler or the store
+        for frequency in [0, u64::MAX] {
+            let request = post_request(feed(frequency), None);
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        assert_eq!(sender.count(), 0);
+
+        let request = post_request(feed(10), None);
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let id = created_feed(response).await.id;
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::PUT)
+                    .uri(format!("/feed/{}", id))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(feed(0)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(sender.count(), 1);
+
+        let request = Request::builder()
+            .uri(format!("/feed/{}", id))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(json(response).await["frequency"], 10);
+    }
+
+    #[tokio::test]
+    async fn invalid_header() {
+        let feed = |name: &str| CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::from([(name.to_string(), "key".to_string())]),
+            staleness: None,
+            archive: None,
+        };
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let router = app(interface);
+
+        let request = post_request(feed("x api key"), None);
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(sender.count(), 0);
+
+        let request = post_request(feed("x-api-key"), None);
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let id = created_feed(response).await.id;
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::PUT)
+                    .uri(format!("/feed/{}", id))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(feed("x api key")))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(sender.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_delete() {
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        // The task of the feed fills the queue, which is never drained
+        let limits = Limits {
+            queue_depth: 1,
+            ..Limits::default()
+        };
+        let (sender, _receiver) = channel::<AsyncTask>(limits);
+        let interface = Arc::new(SchedulerInterface::new(sender, SchedulerHandle::default()));
+        let router = app(interface);
+
+        let response = router
+            .clone()
+            .oneshot(post_request(input, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::DELETE)
+                    .uri("/feed/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // The feed is still running, so it can still be deleted later
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/feed/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let router = app(interface);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/feed/1/history")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/feed")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(input))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/feed/1/history")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let runs: Vec<Run> = serde_json::from_slice(&body).unwrap();
+        assert!(runs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fetch() {
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let router = app(interface);
+        let fetch = || {
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/feed/1/fetch")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(fetch()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(sender.count(), 0);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/feed")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(input))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = router.oneshot(fetch()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(sender.count(), 2);
+        let task = sender.tasks.lock().unwrap().pop().unwrap();
+        assert_eq!(task.op(), Operation::Trigger);
+        assert_eq!(task.id(), 1);
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(sender, SchedulerHandle::default()));
+        let router = app(interface);
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("tulsa_registered_tasks 0\n"));
+    }
+
+    fn post_request(input: CreateFeed, key: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(http::Method::POST)
+            .uri("/feed")
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+        if let Some(key) = key {
+            request = request.header("Idempotency-Key", key);
+        }
+        request.body(Body::from(input)).unwrap()
+    }
+
+    async fn created_feed(response: axum::response::Response) -> Feed {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_posts() {
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let router = app(interface);
+
+        let requests: Vec<_> = (0..20)
+            .map(|_| tokio::spawn(router.clone().oneshot(post_request(input.clone(), None))))
+            .collect();
+        let mut ids = Vec::new();
+        for request in requests {
+            ids.push(created_feed(request.await.unwrap().unwrap()).await.id);
+        }
+
+        ids.sort();
+        assert_eq!(ids, (1..=20).collect::<Vec<_>>());
+        assert_eq!(sender.count(), 20);
+    }
+
+    #[tokio::test]
+    async fn idempotency_key() {
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let router = app(interface);
+
+        // A retry returns the feed which was created, without scheduling another fetch
+        let response = router
+            .clone()
+            .oneshot(post_request(input.clone(), Some("abc")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let first = created_feed(response).await;
+        let response = router
+            .clone()
+            .oneshot(post_request(input.clone(), Some("abc")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(created_feed(response).await.id, first.id);
+        assert_eq!(sender.count(), 1);
+
+        // Another key creates another feed
+        let response = router
+            .clone()
+            .oneshot(post_request(input, Some("def")))
+            .await
+            .unwrap();
+        assert_eq!(created_feed(response).await.id, first.id + 1);
+        assert_eq!(sender.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn expired_idempotency_key() {
+        let path = std::env::temp_dir().join(format!("tulsa-keys-{}.json", std::process::id()));
+        let feed = |id: usize| {
+            serde_json::json!({
+                "id": id, "name": "Name", "url": "http", "frequency": 10, "headers": {}
+            })
+        };
+        let created_at = |age: u64| {
+            let secs = unix_now() - age;
+            serde_json::json!({ "secs_since_epoch": secs, "nanos_since_epoch": 0 })
+        };
+        // One key was sent more than a day ago and the other a minute ago
+        let stored = serde_json::json!({
+            "next_id": 3,
+            "feeds": { "1": feed(1), "2": feed(2) },
+            "keys": {
+                "old": { "feed_id": 1, "created_at": created_at(24 * 60 * 60 + 60) },
+                "new": { "feed_id": 2, "created_at": created_at(60) },
+            },
+        });
+        std::fs::write(&path, stored.to_string()).unwrap();
+
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let interface = Arc::new(SchedulerInterface::new(
+            MockSender::new(),
+            SchedulerHandle::default(),
+        ));
+        let store = Arc::new(FileStore::open(&path).unwrap());
+        let router = app_with_store(interface, store);
+
+        let response = router
+            .clone()
+            .oneshot(post_request(input.clone(), Some("new")))
+            .await
+            .unwrap();
+        assert_eq!(created_feed(response).await.id, 2);
+
+        // The expired key creates another feed, and is no longer kept for the old one
+        let response = router
+            .oneshot(post_request(input, Some("old")))
+            .await
+            .unwrap();
+        assert_eq!(created_feed(response).await.id, 3);
+        let stored: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(stored["keys"]["old"]["feed_id"], 3);
+        assert_eq!(stored["keys"]["new"]["feed_id"], 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_store() {
+        let path = std::env::temp_dir().join(format!("tulsa-feeds-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let input = CreateFeed {
+            name: "Name".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(sender, SchedulerHandle::default()));
+        let store = Arc::new(FileStore::open(&path).unwrap());
+        let response = app_with_store(interface, store)
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/feed")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(input))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // After a restart, the feed is loaded from the file and scheduled again
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let store = Arc::new(FileStore::open(&path).unwrap());
+        assert_eq!(store.list().unwrap().len(), 1);
+        let response = app_with_store(interface, store)
+            .oneshot(
+                Request::builder()
+                    .uri("/feed/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(sender.count(), 1);
+        assert_eq!(sender.tasks.lock().unwrap()[0].id, 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unwritable_store() {
+        let path =
+            std::env::temp_dir().join(format!("tulsa-unwritable-{}.json", std::process::id()));
+        let input = |name: &str| CreateFeed {
+            name: name.to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let _ = std::fs::remove_file(&path);
+        let store = Arc::new(FileStore::open(&path).unwrap());
+        let feed = Feed {
+            name: "Old".to_string(),
+            ..Feed::test("http")
+        };
+        store.insert(feed).unwrap();
+
+        // The store can no longer move its temporary file into place
+        let tmp = path.with_extension("tmp");
+        std::fs::create_dir_all(&tmp).unwrap();
+
+        let sender = MockSender::new();
+        let interface = Arc::new(SchedulerInterface::new(
+            sender.clone(),
+            SchedulerHandle::default(),
+        ));
+        let router = app_with_store(interface, store);
+        assert_eq!(sender.count(), 1);
+
+        // A feed which cannot be stored is stopped again
+        let response = router
+            .clone()
+            .oneshot(post_request(input("New"), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        {
+            let tasks = sender.tasks.lock().unwrap();
+            assert_eq!(tasks.len(), 3);
+            assert_eq!((tasks[1].id, tasks[1].op), (2, Operation::Create));
+            assert_eq!((tasks[2].id, tasks[2].op), (2, Operation::Delete));
+        }
+
+        // An update which cannot be stored goes back to the stored feed
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::PUT)
+                    .uri("/feed/1")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(input("New")))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        {
+            let tasks = sender.tasks.lock().unwrap();
+            assert_eq!(tasks.len(), 5);
+            assert_eq!(tasks[3].op, Operation::Update);
+            assert_eq!(tasks[3].spec.as_ref().unwrap()["name"], "New");
+            assert_eq!(tasks[4].op, Operation::Update);
+            assert_eq!(tasks[4].spec.as_ref().unwrap()["name"], "Old");
+        }
+
+        let response = router
+            .oneshot(Request::builder().uri("/feed").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let feeds = json(response).await;
+        assert_eq!(feeds.as_array().unwrap().len(), 1);
+        assert_eq!(feeds[0]["name"], "Old");
+
+        std::fs::remove_dir(&tmp).unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /// An interface whose feed 1 was fetched from the fixture and feed 2 was not fetched yet.
+    fn fetched_interface() -> Arc<SchedulerInterface<MockSender<AsyncTask>, AsyncTask>> {
+        let raw = std::fs::read("fixtures/gtfs-07132023-123501").unwrap();
+        let message = FeedMessage::decode(raw.as_slice()).unwrap();
+        let cache = Arc::new(FeedCache::new());
+        cache.insert(1, raw.into(), message);
+        cached_interface(cache)
+    }
+
+    /// An interface with feeds 1 and 2, which has the messages of `cache`.
+    fn cached_interface(
+        cache: Arc<FeedCache>,
+    ) -> Arc<SchedulerInterface<MockSender<AsyncTask>, AsyncTask>> {
+        let feeds = (1..=2)
+            .map(|id| Feed {
+                id,
+                name: "Name".to_string(),
+                ..Feed::test("http")
+            })
+            .collect();
+        Arc::new(
+            SchedulerInterface::new(MockSender::new(), SchedulerHandle::default())
+                .with_cache(cache)
+                .with_restored(feeds),
+        )
+    }
+
+    async fn json(response: axum::response::Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn latest() {
+        let router = app(fetched_interface());
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/feed/1/latest")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let latest = json(response).await;
+        assert_eq!(latest["timestamp"], 1689266098);
+        assert_eq!(latest["counts"]["trip_updates"], 243);
+        assert_eq!(latest["message"]["header"]["timestamp"], 1689266098);
+
+        // The message is served as it was fetched to a client which accepts protobuf
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/feed/1/latest")
+                    .header(http::header::ACCEPT, "application/x-protobuf")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/x-protobuf"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            body.to_vec(),
+            std::fs::read("fixtures/gtfs-07132023-123501").unwrap()
+        );
+
+        // Nothing was fetched for feed 2, and there is no feed 3
+        for uri in ["/feed/2/latest", "/feed/3/latest"] {
+            let response = router
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn trip_updates() {
+        let router = app(fetched_interface());
+        let get = |uri: &str| {
+            router
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = get("/feed/1/trip_updates").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json(response).await.as_array().unwrap().len(), 243);
+
+        let response = get("/feed/1/trip_updates?route_id=1&start_date=20230713")
+            .await
+            .unwrap();
+        let updates = json(response).await;
+        let updates = updates.as_array().unwrap();
+        assert_eq!(updates.len(), 31);
+        assert!(updates
+            .iter()
+            .all(|update| update["trip"]["route_id"] == "1"));
+
+        // Every filter has to match, and no trip of the fixture has a direction
+        for uri in [
+            "/feed/1/trip_updates?route_id=1&start_date=20230714",
+            "/feed/1/trip_updates?route_id=1&direction_id=0",
+        ] {
+            let response = get(uri).await.unwrap();
+            assert_eq!(json(response).await, serde_json::json!([]));
+        }
+
+        let response = get("/feed/1/trip_updates?trip_id=069800_1..N04R")
+            .await
+            .unwrap();
+        let updates = json(response).await;
+        assert_eq!(updates.as_array().unwrap().len(), 1);
+        let update = &updates[0];
+        assert_eq!(update["entity_id"], "000001");
+        assert_eq!(update["trip"]["schedule_relationship"], "SCHEDULED");
+        assert_eq!(update["vehicle"], serde_json::Value::Null);
+        let stop = &update["stop_time_updates"][0];
+        assert_eq!(stop["stop_id"], "104N");
+        assert_eq!(stop["arrival"]["time"], 1689266112);
+        assert_eq!(stop["departure"]["time"], 1689266112);
+        assert_eq!(stop["schedule_relationship"], "SCHEDULED");
+
+        let response = get("/feed/1/trip_updates/069800_1..N04R").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json(response).await, *update);
+
+        let response = get("/feed/1/trip_updates?direction_id=north")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // An unknown trip, a feed without a message and a feed which does not exist
+        for uri in [
+            "/feed/1/trip_updates/unknown",
+            "/feed/2/trip_updates",
+            "/feed/3/trip_updates",
+        ] {
+            let response = get(uri).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn arrivals() {
+        use crate::fetcher::transit::{
+            trip_descriptor::ScheduleRelationship as TripRelationship,
+            trip_update::{
+                stop_time_update::ScheduleRelationship as StopRelationship, StopTimeEvent,
+            },
+            VehicleDescriptor,
+        };
+
+        let raw = std::fs::read("fixtures/gtfs-07132023-123501").unwrap();
+        let message = FeedMessage::decode(raw.as_slice()).unwrap();
+
+        // Feed 2 has three of the trips of feed 1: one canceled, one which skips the stop and
+        // one with a label, whose arrivals only have a delay and whose departures have the time
+        let mut other = message.clone();
+        other.entity.retain_mut(|entity| {
+            let Some(update) = entity.trip_update.as_mut() else {
+                return false;
+            };
+            match update.trip.trip_id.as_deref() {
+                Some("071150_2..N01R") => {
+                    update
+                        .trip
+                        .set_schedule_relationship(TripRelationship::Canceled);
+                }
+                Some("071850_2..N01R") => {
+                    for stop in update.stop_time_update.iter_mut() {
+                        stop.set_schedule_relationship(StopRelationship::Skipped);
+                    }
+                }
+                Some("072700_2..N01R") => {
+                    update.vehicle = Some(VehicleDescriptor {
+                        label: Some("Wakefield-241 St".to_string()),
+                        ..Default::default()
+                    });
+                    for stop in update.stop_time_update.iter_mut() {
+                        stop.departure = stop.arrival.take();
+                        stop.arrival = Some(StopTimeEvent {
+                            delay: Some(60),
+                            ..Default::default()
+                        });
+                    }
+                }
+                _ => return false,
+            }
+            true
+        });
+        assert_eq!(other.entity.len(), 3);
+
+        let cache = Arc::new(FeedCache::new());
+        cache.insert(1, raw.into(), message);
+        cache.insert(2, other.encode_to_vec().into(), other);
+        let router = app(cached_interface(cache));
+        let get = |uri: &str| {
+            router
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = get("/stops/127N/arrivals?after=1689266098").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let arrivals = json(response).await;
+        let arrivals = arrivals.as_array().unwrap();
+        // An arrival before `after` is left out
+        assert_eq!(arrivals.len(), 29);
+        assert!(arrivals
+            .windows(2)
+            .all(|pair| pair[0]["time"].as_i64() <= pair[1]["time"].as_i64()));
+        assert_eq!(arrivals[0]["feed_id"], 1);
+        assert_eq!(arrivals[0]["route_id"], "2");
+        assert_eq!(arrivals[0]["trip_id"], "071150_2..N01R");
+        assert_eq!(arrivals[0]["time"], 1689266113);
+
+        let from_other: Vec<_> = arrivals
+            .iter()
+            .filter(|arrival| arrival["feed_id"] == 2)
+            .collect();
+        assert_eq!(from_other.len(), 1);
+        assert_eq!(from_other[0]["trip_id"], "072700_2..N01R");
+        assert_eq!(from_other[0]["headsign"], "Wakefield-241 St");
+        assert_eq!(from_other[0]["time"], 1689266929);
+
+        let response = get("/stops/127N/arrivals?after=1689266098&limit=3")
+            .await
+            .unwrap();
+        let limited = json(response).await;
+        assert_eq!(limited.as_array().unwrap()[..], arrivals[..3]);
+
+        // Every arrival of the fixture is in the past, and nothing stops at an unknown stop
+        for uri in ["/stops/127N/arrivals", "/stops/unknown/arrivals?after=0"] {
+            let response = get(uri).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(json(response).await, serde_json::json!([]));
+        }
+    }
+
+    #[tokio::test]
+    async fn vehicles() {
+        use crate::fetcher::transit::{vehicle_position::OccupancyStatus, Position};
+
+        let raw = std::fs::read("fixtures/gtfs-07132023-123501").unwrap();
+        let mut message = FeedMessage::decode(raw.as_slice()).unwrap();
+
+        // The subway does not report positions, so place the first two vehicles of route 1
+        let mut placed = message
+            .entity
+            .iter_mut()
+            .filter_map(|entity| entity.vehicle.as_mut())
+            .filter(|vehicle| vehicle.trip.as_ref().unwrap().route_id.as_deref() == Some("1"));
+        let first = placed.next().unwrap();
+        first.position = Some(Position {
+            latitude: 40.8,
+            longitude: -73.96,
+            bearing: Some(180.0),
+            speed: Some(12.5),
+            ..Default::default()
+        });
+        first.set_occupancy_status(OccupancyStatus::FewSeatsAvailable);
+        placed.next().unwrap().position = Some(Position {
+            latitude: 40.6,
+            longitude: -74.01,
+            ..Default::default()
+        });
+
+        let cache = Arc::new(FeedCache::new());
+        cache.insert(1, raw.into(), message);
+        let router = app(cached_interface(cache));
+        let get = |uri: &str| {
+            router
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = get("/feed/1/vehicles").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/geo+json"
+        );
+        let collection = json(response).await;
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 156);
+        assert_eq!(features[0]["type"], "Feature");
+        assert_eq!(features[0]["id"], "000002");
+        assert_eq!(features[0]["properties"]["stop_id"], "104N");
+        assert_eq!(features[0]["properties"]["current_status"], "STOPPED_AT");
+        assert_eq!(
+            features[0]["properties"]["congestion_level"],
+            serde_json::Value::Null
+        );
+        assert_eq!(features[0]["properties"]["trip"]["route_id"], "1");
+
+        let response = get("/feed/1/vehicles?route_id=1").await.unwrap();
+        let collection = json(response).await;
+        let features = collection["features"].as_array().unwrap();
+        assert!(!features.is_empty() && features.len() < 156);
+        assert!(features
+            .iter()
+            .all(|feature| feature["properties"]["trip"]["route_id"] == "1"));
+
+        // Only the first placed vehicle is in the box, and vehicles without a position are not
+        let response = get("/feed/1/vehicles?bbox=-74.0,40.7,-73.9,40.9")
+            .await
+            .unwrap();
+        let collection = json(response).await;
+        assert_eq!(collection["features"].as_array().unwrap().len(), 1);
+        let feature = &collection["features"][0];
+        assert_eq!(
+            feature["geometry"],
+            serde_json::json!({"type": "Point", "coordinates": [-73.96, 40.8]})
+        );
+        assert_eq!(feature["properties"]["bearing"], 180.0);
+        assert_eq!(feature["properties"]["speed"], 12.5);
+        assert_eq!(
+            feature["properties"]["occupancy_status"],
+            "FEW_SEATS_AVAILABLE"
+        );
+
+        let response = get("/feed/1/vehicles?route_id=2&bbox=-75,40,-73,41")
+            .await
+            .unwrap();
+        assert_eq!(json(response).await["features"], serde_json::json!([]));
+
+        for bbox in ["1,2,3", "-73.9,40.7,-74.0,40.9", "a,b,c,d"] {
+            let response = get(&format!("/feed/1/vehicles?bbox={}", bbox))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = get("/feed/2/vehicles").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn alerts() {
+        use crate::fetcher::transit::{
+            alert::{Cause, Effect},
+            translated_string::Translation,
+            Alert, EntitySelector, FeedEntity, FeedHeader, TimeRange, TranslatedString,
+            TripDescriptor,
+        };
+
+        fn text(translations: &[(Option<&str>, &str)]) -> Option<TranslatedString> {
+            Some(TranslatedString {
+                translation: translations
+                    .iter()
+                    .map(|(language, text)| Translation {
+                        text: text.to_string(),
+                        language: language.map(str::to_string),
+                    })
+                    .collect(),
+            })
+        }
+
+        let mut construction = Alert {
+            active_period: vec![TimeRange {
+                start: Some(1000),
+                end: Some(2000),
+            }],
+            informed_entity: vec![
+                EntitySelector {
+                    route_id: Some("1".to_string()),
+                    ..Default::default()
+                },
+                EntitySelector {
+                    stop_id: Some("127N".to_string()),
+                    ..Default::default()
+                },
+            ],
+            header_text: text(&[
+                (None, "Trains run express"),
+                (Some("es"), "Los trenes son expresos"),
+            ]),
+            ..Default::default()
+        };
+        construction.set_cause(Cause::Construction);
+        construction.set_effect(Effect::ModifiedService);
+        // An alert without an active period is always active
+        let delays = Alert {
+            informed_entity: vec![EntitySelector {
+                agency_id: Some("MTASBWY".to_string()),
+                trip: Some(TripDescriptor {
+                    trip_id: Some("069800_1..N04R".to_string()),
+                    route_id: Some("2".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            header_text: text(&[(Some("en"), "Delays"), (Some("fr"), "Retards")]),
+            ..Default::default()
+        };
+        let message = FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: "2.0".to_string(),
+                ..Default::default()
+            },
+            entity: [("construction", construction), ("delays", delays)]
+                .into_iter()
+                .map(|(id, alert)| FeedEntity {
+                    id: id.to_string(),
+                    alert: Some(alert),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+
+        let cache = Arc::new(FeedCache::new());
+        cache.insert(1, message.encode_to_vec().into(), message);
+        let router = app(cached_interface(cache));
+        let get = |uri: &str, accept_language: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(accept_language) = accept_language {
+                request = request.header(http::header::ACCEPT_LANGUAGE, accept_language);
+            }
+            router.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        let ids = |alerts: serde_json::Value| -> Vec<String> {
+            alerts
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|alert| alert["entity_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let response = get("/feed/1/alerts", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let alerts = json(response).await;
+        assert_eq!(alerts[0]["cause"], "CONSTRUCTION");
+        assert_eq!(alerts[0]["effect"], "MODIFIED_SERVICE");
+        assert_eq!(alerts[0]["severity_level"], "UNKNOWN_SEVERITY");
+        assert_eq!(alerts[0]["active_periods"][0]["end"], 2000);
+        assert_eq!(alerts[0]["header_text"], "Trains run express");
+        // Without an untranslated text the first translation is used
+        assert_eq!(alerts[1]["header_text"], "Delays");
+        assert_eq!(ids(alerts), ["construction", "delays"]);
+
+        let cases = [
+            (
+                "/feed/1/alerts?active_at=1500",
+                vec!["construction", "delays"],
+            ),
+            ("/feed/1/alerts?active_at=2001", vec!["delays"]),
+            ("/feed/1/alerts?active=true", vec!["delays"]),
+            ("/feed/1/alerts?route_id=1", vec!["construction"]),
+            // The route of a trip informs about the route
+            ("/feed/1/alerts?route_id=2", vec!["delays"]),
+            (
+                "/feed/1/alerts?stop_id=127N&route_id=1",
+                vec!["construction"],
+            ),
+            ("/feed/1/alerts?trip_id=069800_1..N04R", vec!["delays"]),
+            ("/feed/1/alerts?agency_id=MTASBWY", vec!["delays"]),
+            ("/feed/1/alerts?cause=construction", vec!["construction"]),
+            ("/feed/1/alerts?effect=UNKNOWN_EFFECT", vec!["delays"]),
+            ("/feed/1/alerts?severity=SEVERE", vec![]),
+            ("/feed/1/alerts?stop_id=127N&trip_id=069800_1..N04R", vec![]),
+        ];
+        for (uri, expected) in cases {
+            let response = get(uri, None).await.unwrap();
+            assert_eq!(ids(json(response).await), expected, "{}", uri);
+        }
+
+        let response = get("/feed/1/alerts", Some("fr-CA, es;q=0.5"))
+            .await
+            .unwrap();
+        let alerts = json(response).await;
+        assert_eq!(alerts[0]["header_text"], "Los trenes son expresos");
+        assert_eq!(alerts[1]["header_text"], "Retards");
+
+        // The query parameter takes precedence over the header
+        let response = get("/feed/1/alerts?lang=es", Some("fr")).await.unwrap();
+        let alerts = json(response).await;
+        assert_eq!(alerts[0]["header_text"], "Los trenes son expresos");
+        assert_eq!(alerts[1]["header_text"], "Delays");
+
+        let response = get("/feed/1/alerts?active_at=soon", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get("/feed/2/alerts", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn feed_status() {
+        let health = Arc::new(FeedHealth::new());
+        let ok = Summary {
+            num_trip_updates: 243,
+            timestamp: Some(1689266098),
+            status: 200,
+        };
+        let failed = FetchError {
+            kind: FetchErrorKind::Http,
+            status: Some(403),
+            message: "HTTP status 403".to_string(),
+        };
+        health.record(1, None, &Ok(ok));
+        health.record(1, None, &Err(failed.clone()));
+        health.record(1, None, &Err(failed));
+
+        let feeds = (1..=3)
+            .map(|id| Feed {
+                id,
+                name: "Name".to_string(),
+                ..Feed::test("http")
+            })
+            .collect();
+        let interface = SchedulerInterface::new(MockSender::new(), SchedulerHandle::default())
+            .with_health(health.clone())
+            .with_restored(feeds);
+        let router = app(Arc::new(interface));
+        let get = |uri: &str| {
+            router
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = get("/feed/1/status").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let status = json(response).await;
+        assert_eq!(status["state"], "failing");
+        assert_eq!(status["status_code"], 403);
+        assert_eq!(status["error"]["kind"], "http");
+        assert_eq!(status["consecutive_failures"], 2);
+        assert!(status["last_success_at"].as_u64() <= status["last_attempt_at"].as_u64());
+
+        // A success resets the failures
+        health.record(
+            1,
+            None,
+            &Ok(Summary {
+                num_trip_updates: 0,
+                timestamp: None,
+                status: 200,
+            }),
+        );
+        let status = json(get("/feed/1/status").await.unwrap()).await;
+        assert_eq!(status["state"], "healthy");
+        assert_eq!(status["status_code"], 200);
+        assert_eq!(status["error"], serde_json::Value::Null);
+        assert_eq!(status["consecutive_failures"], 0);
+
+        // A feed which keeps returning an old message is stale
+        let staleness = Staleness {
+            max_header_age: Some(60),
+            max_without_success: None,
+        };
+        let old = Summary {
+            num_trip_updates: 243,
+            timestamp: Some(1689266098),
+            status: 200,
+        };
+        health.record(3, Some(&staleness), &Ok(old));
+        let status = json(get("/feed/3/status").await.unwrap()).await;
+        assert_eq!(status["state"], "stale");
+        assert_eq!(status["stale"], serde_json::json!(["header_age"]));
+        assert_eq!(status["header_timestamp"], 1689266098);
+
+        let status = json(get("/feed/2/status").await.unwrap()).await;
+        assert_eq!(status["state"], "unknown");
+        assert_eq!(status["last_attempt_at"], serde_json::Value::Null);
+
+        let response = get("/feed/4/status").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The list has the feeds with a summary of their health
+        let feeds = json(get("/feed").await.unwrap()).await;
+        assert_eq!(feeds[0]["id"], 1);
+        assert_eq!(feeds[0]["name"], "Name");
+        assert_eq!(feeds[0]["health"]["state"], "healthy");
+        assert_eq!(feeds[0]["health"]["consecutive_failures"], 0);
+        assert_eq!(feeds[1]["health"]["state"], "unknown");
+        assert_eq!(feeds.as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn restore() {
+        let feed = Feed {
+            id: 7,
+            name: "Name".to_string(),
+            ..Feed::test("http")
+        };
+        let sender = MockSender::new();
+        let interface = Arc::new(
+            SchedulerInterface::new(sender.clone(), SchedulerHandle::default())
+                .with_restored(vec![feed]),
+        );
+        let router = app(interface);
+
+        // The restored feed is scheduled and stored under its own id
+        assert_eq!(sender.count(), 1);
+        let spec = sender.tasks.lock().unwrap()[0].spec.clone().unwrap();
+        assert_eq!(spec["url"], "http");
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/feed/7")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // New feeds are numbered after it
+        let input = CreateFeed {
+            name: "Other".to_string(),
+            url: "http".to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
+        };
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/feed")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(input))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: Feed = serde_json::from_slice(&body).unwrap();
+        assert_eq!(created.id, 8);
+
+        let response = router
+            .oneshot(
+                Request::builder()
+                    .uri("/snapshot")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let snapshot: tulsa::Snapshot = serde_json::from_slice(&body).unwrap();
+        assert!(snapshot.tasks.is_empty());
+    }
+
     #[tokio::test]
     async fn full_api_flow() {
         let input = CreateFeed {
@@ -417,16 +2051,20 @@ mod api_tests {
             url: "http".to_string(),
             frequency: 10,
             headers: HashMap::new(),
+            staleness: None,
+            archive: None,
         };
         let input_new = CreateFeed {
             name: "Name".to_string(),
             url: "http".to_string(),
             frequency: 20,
             headers: HashMap::new(),
+            staleness: None,
+            archive: None,
         };
 
         let sender = MockSender::new();
-        let interface = Arc::new(SchedulerInterface::new(sender));
+        let interface = Arc::new(SchedulerInterface::new(sender, SchedulerHandle::default()));
         let address = SocketAddr::from(([0, 0, 0, 0], 3000));
         tokio::spawn(async move {
             let listener = TcpListener::bind(address).await.unwrap();
@@ -0,0 +1,262 @@
+use chrono::Utc;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::models::{Archive, Feed};
+
+/// The fetched payloads of the feeds which ask for it are archived under this directory when the
+/// variable is set.
+const ARCHIVE_DIR_VAR: &str = "TULSA_ARCHIVE_DIR";
+
+/// Every archived payload is named after when it was fetched, in UTC, like the fixtures.
+const NAME_FORMAT: &str = "gtfs-%m%d%Y-%H%M%S";
+
+/// Keeps the payloads of feeds as they were fetched, in a directory for each feed.
+#[derive(Debug, Default)]
+pub struct Archiver {
+    /// `None` if nothing is archived.
+    dir: Option<PathBuf>,
+}
+
+impl Archiver {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    /// Write `payload` of `feed` to its directory, if the feed has an `Archive`, and drop the
+    /// payloads its retention no longer keeps. Returns the path of the new file.
+    pub fn archive(&self, feed: &Feed, payload: &[u8]) -> io::Result<Option<PathBuf>> {
+        let (Some(dir), Some(archive)) = (&self.dir, &feed.archive) else {
+            return Ok(None);
+        };
+        let dir = dir.join(feed.id.to_string());
+        fs::create_dir_all(&dir)?;
+
+        let path = write(&dir, archive, payload)?;
+        prune(&dir, archive, &path)?;
+        Ok(Some(path))
+    }
+}
+
+/// Write `payload` to a new file in `dir`. A second payload within the same second gets a suffix,
+/// so that no payload is overwritten.
+fn write(dir: &Path, archive: &Archive, payload: &[u8]) -> io::Result<PathBuf> {
+    let name = Utc::now().format(NAME_FORMAT).to_string();
+    let extension = if archive.compress { ".gz" } else { "" };
+    let mut path = dir.join(format!("{}{}", name, extension));
+    let mut suffix = 0;
+    let mut file = loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => break file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                suffix += 1;
+                path = dir.join(format!("{}-{}{}", name, suffix, extension));
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    if archive.compress {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(payload)?;
+        encoder.finish()?.sync_all()?;
+    } else {
+        file.write_all(payload)?;
+        file.sync_all()?;
+    }
+    Ok(path)
+}
+
+/// Remove the payloads in `dir` which are older than `max_age`, then the oldest ones until the
+/// rest fit in `max_bytes`. The payload at `newest` is always kept.
+fn prune(dir: &Path, archive: &Archive, newest: &Path) -> io::Result<()> {
+    if archive.max_age.is_none() && archive.max_bytes.is_none() {
+        return Ok(());
+    }
+
+    let mut payloads = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && entry.file_name().to_string_lossy().starts_with("gtfs-") {
+            payloads.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+    }
+    payloads.sort();
+
+    let now = SystemTime::now();
+    let mut total: u64 = payloads.iter().map(|(_, len, _)| len).sum();
+    for (modified, len, path) in payloads {
+        if path == newest {
+            continue;
+        }
+        let age = now.duration_since(modified).unwrap_or_default();
+        let expired = archive
+            .max_age
+            .is_some_and(|max| age > Duration::from_secs(max));
+        let too_large = archive.max_bytes.is_some_and(|max| total > max);
+        if expired || too_large {
+            fs::remove_file(&path)?;
+            total -= len;
+        }
+    }
+    Ok(())
+}
+
+/// An `Archiver` into `TULSA_ARCHIVE_DIR`, which archives nothing if it is not set.
+pub fn build() -> Archiver {
+    match env::var(ARCHIVE_DIR_VAR) {
+        Ok(dir) => Archiver::new(dir),
+        Err(_) => Archiver::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+    use std::{fs::File, io::Read};
+
+    use super::*;
+
+    fn feed(id: usize, archive: Archive) -> Feed {
+        Feed {
+            id,
+            frequency: 5,
+            archive: Some(archive),
+            ..Feed::test("http")
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tulsa-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn payloads() {
+        let dir = temp_dir("archive");
+        let archiver = Archiver::new(&dir);
+
+        let raw = archiver
+            .archive(&feed(1, Archive::default()), b"raw")
+            .unwrap()
+            .unwrap();
+        assert!(raw.starts_with(dir.join("1")));
+        assert!(raw
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("gtfs-"));
+        assert_eq!(fs::read(&raw).unwrap(), b"raw");
+
+        let compressed = Archive {
+            compress: true,
+            ..Archive::default()
+        };
+        let gz = archiver
+            .archive(&feed(1, compressed), b"compressed")
+            .unwrap()
+            .unwrap();
+        assert_eq!(gz.extension().unwrap(), "gz");
+        let mut payload = Vec::new();
+        GzDecoder::new(File::open(&gz).unwrap())
+            .read_to_end(&mut payload)
+            .unwrap();
+        assert_eq!(payload, b"compressed");
+
+        // Payloads within the same second do not overwrite each other
+        let again = archiver
+            .archive(&feed(1, Archive::default()), b"again")
+            .unwrap()
+            .unwrap();
+        assert_ne!(again, raw);
+        assert_eq!(fs::read(&raw).unwrap(), b"raw");
+
+        // Nothing is archived for a feed without an `Archive`, or without a directory
+        let mut unarchived = feed(2, Archive::default());
+        unarchived.archive = None;
+        assert_eq!(archiver.archive(&unarchived, b"raw").unwrap(), None);
+        assert!(!dir.join("2").exists());
+        let disabled = Archiver::default();
+        assert_eq!(
+            disabled
+                .archive(&feed(3, Archive::default()), b"raw")
+                .unwrap(),
+            None
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention() {
+        let dir = temp_dir("retention");
+        let archiver = Archiver::new(&dir);
+        let feed_dir = dir.join("1");
+        fs::create_dir_all(&feed_dir).unwrap();
+
+        // An hour old payload, a recent one, and a file which is not a payload
+        let now = SystemTime::now();
+        for (name, age) in [("gtfs-old", 3600), ("gtfs-recent", 10)] {
+            let file = File::create(feed_dir.join(name)).unwrap();
+            file.set_len(100).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        fs::write(feed_dir.join("notes.txt"), vec![0; 1000]).unwrap();
+
+        let max_age = Archive {
+            max_age: Some(60),
+            ..Archive::default()
+        };
+        let newest = archiver
+            .archive(&feed(1, max_age), &[0; 100])
+            .unwrap()
+            .unwrap();
+        let newest = newest.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            names(&feed_dir),
+            [newest.as_str(), "gtfs-recent", "notes.txt"]
+        );
+
+        // The oldest payloads go first, and the newest one stays even if it is too large
+        let max_bytes = Archive {
+            max_bytes: Some(250),
+            ..Archive::default()
+        };
+        archiver.archive(&feed(1, max_bytes), &[0; 100]).unwrap();
+        let kept = names(&feed_dir);
+        assert_eq!(kept.len(), 3);
+        assert!(!kept.contains(&"gtfs-recent".to_string()));
+
+        let tiny = Archive {
+            max_bytes: Some(1),
+            ..Archive::default()
+        };
+        let last = archiver
+            .archive(&feed(1, tiny), &[0; 100])
+            .unwrap()
+            .unwrap();
+        let last = last.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(names(&feed_dir), [last.as_str(), "notes.txt"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use prost::bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use crate::fetcher::transit::FeedMessage;
+
+/// The most recent message fetched for a feed.
+#[derive(Debug)]
+pub struct Latest {
+    pub fetched_at: SystemTime,
+    /// The message as it was fetched, which is served to clients who ask for protobuf.
+    pub raw: Bytes,
+    pub message: FeedMessage,
+}
+
+/// Keeps the most recent message of each feed. The fetch tasks write it and the API reads it.
+#[derive(Debug, Default)]
+pub struct FeedCache {
+    feeds: RwLock<HashMap<usize, Arc<Latest>>>,
+}
+
+impl FeedCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: usize) -> Option<Arc<Latest>> {
+        self.feeds.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn insert(&self, id: usize, raw: Bytes, message: FeedMessage) {
+        let latest = Latest {
+            fetched_at: SystemTime::now(),
+            raw,
+            message,
+        };
+        self.feeds.write().unwrap().insert(id, Arc::new(latest));
+    }
+
+    pub fn remove(&self, id: usize) {
+        self.feeds.write().unwrap().remove(&id);
+    }
+}
@@ -29,7 +29,10 @@ impl Server {
     }
 
     pub fn new() -> Server {
-        let address = SocketAddr::from(([127, 0, 0, 1], 5001));
+        // Bind to any free port so that several servers can run at once, like in mockito.
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
         let state = Arc::new(RwLock::new(State::new()));
 
         let runtime = runtime::Builder::new_current_thread()
@@ -40,7 +43,7 @@ impl Server {
         let state_b = state.clone();
         thread::spawn(move || {
             runtime.block_on(async {
-                let listener = TcpListener::bind(address).await.unwrap();
+                let listener = TcpListener::from_std(listener).unwrap();
 
                 while let Ok((stream, _)) = listener.accept().await {
                     let state_c = state_b.clone();
@@ -88,7 +91,8 @@ async fn handle_request(
 
     if let Some(mock) = mock {
         mock.inner.num_called += 1;
-        let response = HyperResponse::new(Full::new(Bytes::from(mock.inner.response.body.clone())));
+        let mut response = HyperResponse::new(Full::new(Bytes::from(mock.inner.response.body.clone())));
+        *response.status_mut() = mock.inner.response.status;
         Ok(response)
     } else {
         panic!("No matching mock found");
@@ -111,6 +111,18 @@ impl Config {
         Config::default()
     }
 
+    /// Add an attribute, such as a derive, to every message and enum which matches `path`. A
+    /// path of `.` matches every type.
+    pub fn type_attribute<P, A>(&mut self, path: P, attribute: A) -> &mut Self
+    where
+        P: AsRef<str>,
+        A: AsRef<str>,
+    {
+        self.type_attributes
+            .insert(path.as_ref().to_string(), attribute.as_ref().to_string());
+        self
+    }
+
     fn compile_fds(&mut self, file_descriptor_set: FileDescriptorSet) -> Result<()> {
         let target: PathBuf = env::var("OUT_DIR").unwrap().into();
 
@@ -1,99 +1,268 @@
 use prost::{bytes::Bytes, Message};
 use reqwest::Client;
-use tokio::time::{Duration, Interval};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, sync::Mutex, time::Duration};
+use tracing::{info, warn};
 use ureq;
 
 use crate::fetcher::transit::FeedMessage;
 
-mod transit {
+/// The GTFS-Realtime types, generated from `src/proto/gtfs-realtime.proto`.
+pub mod transit {
     include!(concat!(env!("OUT_DIR"), "/transit_realtime.rs"));
 }
 
-use crate::models::Feed;
+use crate::{
+    archive::Archiver,
+    cache::FeedCache,
+    models::{Feed, InvalidFeed},
+};
 
-async fn fetch(feed: &Feed) -> usize {
-    println!("Fetching {}", feed.name);
+/// The longest delay between two fetches, as a multiple of the feed frequency.
+const MAX_BACKOFF: u32 = 8;
 
-    let client = Client::new();
+/// How long a fetch may take before it fails, so that a feed which hangs does not hold up its
+/// task.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
 
-    let headers = feed.to_header_map();
-    let response = client
-        .get(&feed.url)
-        .headers(headers)
-        .send()
-        .await
-        .map_err(|e| {
-            eprintln!("Error fetching {}: {}", feed.name, e);
-            e
-        })
-        .unwrap();
+/// What was found in a fetched feed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub num_trip_updates: usize,
+    /// The `FeedHeader.timestamp`, which changes whenever the producer publishes.
+    pub timestamp: Option<u64>,
+    /// The status of the response.
+    pub status: u16,
+}
 
-    let bytes = response
-        .bytes()
-        .await
-        .map_err(|e| eprintln!("Error reading {}: {}", feed.name, e))
-        .unwrap();
+/// Picks the delay before the next fetch of a feed. The delay is halved while the feed keeps
+/// changing and doubled while it does not, between the feed frequency and `MAX_BACKOFF` times
+/// that.
+pub struct Pacer {
+    min: Duration,
+    max: Duration,
+    /// The last timestamp seen and the current delay.
+    state: Mutex<(Option<u64>, Duration)>,
+}
 
-    let b = FeedMessage::decode(bytes)
-        .map_err(|e| eprintln!("Error decoding {}: {}", feed.name, e))
-        .unwrap();
+impl Pacer {
+    pub fn new(feed: &Feed) -> Self {
+        let min = Duration::from_secs(feed.frequency);
+        Self {
+            min,
+            // Saturates instead of overflowing for a very long frequency.
+            max: min.saturating_mul(MAX_BACKOFF),
+            state: Mutex::new((None, min)),
+        }
+    }
+
+    pub fn bounds(&self) -> (Duration, Duration) {
+        (self.min, self.max)
+    }
 
-    let mut num_trip_updates: usize = 0;
-    for e in b.entity {
-        if e.trip_update.is_some() {
-            num_trip_updates += 1;
+    pub fn next(&self, timestamp: Option<u64>) -> Duration {
+        let (last, delay) = &mut *self.state.lock().unwrap();
+        match timestamp {
+            // Without a timestamp there is no way to tell whether the feed changed.
+            None => {}
+            Some(timestamp) if *last == Some(timestamp) => {
+                *delay = delay.saturating_mul(2).min(self.max)
+            }
+            Some(timestamp) => {
+                *last = Some(timestamp);
+                *delay = (*delay / 2).max(self.min);
+            }
         }
+        *delay
     }
-    println!("{}: {} trip updates", feed.name, num_trip_updates);
+}
 
-    num_trip_updates
+/// Why a fetch of a feed failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchErrorKind {
+    /// The feed could not be reached or the response could not be read.
+    Connect,
+    /// The feed took longer than `FETCH_TIMEOUT` to respond.
+    Timeout,
+    /// The feed responded with a status other than success.
+    Http,
+    /// The response is not a GTFS-Realtime message.
+    Decode,
+    /// The request for the feed could not be built, such as from a header which is not valid.
+    Request,
 }
 
-pub fn fetch_sync(feed: &Feed) -> usize {
-    println!("Fetching {}", feed.name);
+/// Used to indicate a fetch of a feed failed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchError {
+    pub kind: FetchErrorKind,
+    /// The status of the response, if there was one.
+    pub status: Option<u16>,
+    pub message: String,
+}
 
-    let mut request = ureq::get(&feed.url);
-    for (key, value) in feed.headers.iter() {
-        request = request.set(key, value);
+impl FetchError {
+    fn new(kind: FetchErrorKind, message: impl ToString) -> Self {
+        Self {
+            kind,
+            status: None,
+            message: message.to_string(),
+        }
     }
 
-    let response = request
-        .call()
-        .map_err(|e| {
-            eprintln!("Error fetching {}: {}", feed.name, e);
-            e
-        })
-        .unwrap();
+    fn http(status: u16) -> Self {
+        Self {
+            kind: FetchErrorKind::Http,
+            status: Some(status),
+            message: format!("HTTP status {}", status),
+        }
+    }
 
-    let mut vec_bytes = Vec::new();
-    response.into_reader().read_to_end(&mut vec_bytes).unwrap();
-    let bytes: Bytes = vec_bytes.into();
+    fn decode(status: u16, e: prost::DecodeError) -> Self {
+        Self {
+            status: Some(status),
+            ..Self::new(FetchErrorKind::Decode, e)
+        }
+    }
+}
 
-    let b = FeedMessage::decode(bytes)
-        .map_err(|e| eprintln!("Error decoding {}: {}", feed.name, e))
-        .unwrap();
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} error: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        let kind = if e.is_timeout() {
+            FetchErrorKind::Timeout
+        } else {
+            FetchErrorKind::Connect
+        };
+        Self::new(kind, e)
+    }
+}
+
+impl From<InvalidFeed> for FetchError {
+    fn from(e: InvalidFeed) -> Self {
+        Self::new(FetchErrorKind::Request, e)
+    }
+}
 
-    let mut num_trip_updates: usize = 0;
-    for e in b.entity {
-        if e.trip_update.is_some() {
-            num_trip_updates += 1;
+impl From<io::Error> for FetchError {
+    fn from(e: io::Error) -> Self {
+        let kind = match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => FetchErrorKind::Timeout,
+            _ => FetchErrorKind::Connect,
+        };
+        Self::new(kind, e)
+    }
+}
+
+impl From<ureq::Error> for FetchError {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(status, _) => Self::http(status),
+            ureq::Error::Transport(transport) => {
+                // ureq reports a timeout as the I/O error it came from.
+                let timed_out = std::error::Error::source(&transport)
+                    .and_then(|source| source.downcast_ref::<io::Error>())
+                    .is_some_and(|e| {
+                        matches!(
+                            e.kind(),
+                            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                        )
+                    });
+                let kind = if timed_out {
+                    FetchErrorKind::Timeout
+                } else {
+                    FetchErrorKind::Connect
+                };
+                Self::new(kind, transport)
+            }
         }
     }
-    println!("{}: {} trip updates", feed.name, num_trip_updates);
+}
+
+fn summarize(feed: &Feed, status: u16, message: &FeedMessage) -> Summary {
+    let timestamp = message.header.timestamp;
+    let num_trip_updates = message
+        .entity
+        .iter()
+        .filter(|e| e.trip_update.is_some())
+        .count();
+    info!("{}: {} trip updates", feed.name, num_trip_updates);
+
+    Summary {
+        num_trip_updates,
+        timestamp,
+        status,
+    }
+}
+
+/// Archive, decode and summarize a payload of `feed`, keeping the message in `cache`. A payload
+/// which cannot be archived is still used.
+fn receive(
+    feed: &Feed,
+    status: u16,
+    bytes: Bytes,
+    cache: &FeedCache,
+    archiver: &Archiver,
+) -> Result<Summary, FetchError> {
+    if let Err(e) = archiver.archive(feed, &bytes) {
+        warn!("Failed to archive {}: {}", feed.name, e);
+    }
+    let message = FeedMessage::decode(bytes.clone()).map_err(|e| FetchError::decode(status, e))?;
 
-    num_trip_updates
+    let summary = summarize(feed, status, &message);
+    cache.insert(feed.id, bytes, message);
+    Ok(summary)
 }
 
-pub async fn recurring_fetch(feed: Feed) {
-    let interval_duration = Duration::from_secs(feed.frequency);
-    let mut interval: Interval = tokio::time::interval(interval_duration);
+/// Fetch and decode `feed`, keeping the message in `cache`.
+pub async fn fetch(
+    feed: &Feed,
+    cache: &FeedCache,
+    archiver: &Archiver,
+) -> Result<Summary, FetchError> {
+    info!("Fetching {}", feed.name);
+
+    let headers = feed.to_header_map()?;
+    let client = Client::builder().timeout(FETCH_TIMEOUT).build()?;
+    let response = client.get(&feed.url).headers(headers).send().await?;
 
-    loop {
-        interval.tick().await;
-        // It might technically be more accurate timer-wise to spawn this
-        // like so: tokio::spawn(fetch(feed.clone()));
-        fetch(&feed).await;
+    let status = response.status().as_u16();
+    if !response.status().is_success() {
+        return Err(FetchError::http(status));
     }
+    let bytes = response.bytes().await?;
+    receive(feed, status, bytes, cache, archiver)
+}
+
+pub fn fetch_sync(
+    feed: &Feed,
+    cache: &FeedCache,
+    archiver: &Archiver,
+) -> Result<Summary, FetchError> {
+    info!("Fetching {}", feed.name);
+
+    // ureq would only report a header which is not valid as a transport error.
+    feed.to_header_map()?;
+    let agent = ureq::AgentBuilder::new().timeout(FETCH_TIMEOUT).build();
+    let mut request = agent.get(&feed.url);
+    for (key, value) in feed.headers.iter() {
+        request = request.set(key, value);
+    }
+
+    // ureq returns every status other than success as an error.
+    let response = request.call()?;
+    let status = response.status();
+    let mut vec_bytes = Vec::new();
+    response.into_reader().read_to_end(&mut vec_bytes)?;
+    receive(feed, status, vec_bytes.into(), cache, archiver)
 }
 
 #[cfg(test)]
@@ -102,9 +271,10 @@ mod tests {
     use crate::deps::mockito;
 
     use super::*;
-    use std::collections::HashMap;
+    use crate::models::Archive;
     use std::fs;
     use std::io::Read;
+    use std::sync::Arc;
     use std::vec::Vec;
 
     #[tokio::test]
@@ -115,6 +285,7 @@ mod tests {
             .unwrap();
         file.read_to_end(&mut buffer)
             .expect("Failed to read the file");
+        let buffer_len = buffer.len();
 
         let mut server = mockito::Server::new_async().await;
         let mock = server
@@ -124,16 +295,143 @@ mod tests {
             .create();
 
         let feed = Feed {
-            id: 1,
-            name: "Test".to_string(),
             frequency: 5,
-            url: format!("{}/gtfs", server.url()),
-            headers: HashMap::new(),
+            archive: Some(Archive::default()),
+            ..Feed::test(format!("{}/gtfs", server.url()))
         };
 
-        let num_found = fetch(&feed).await;
+        let dir = std::env::temp_dir().join(format!("tulsa-fetcher-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let archiver = Arc::new(Archiver::new(&dir));
+        let cache = FeedCache::new();
+        let summary = fetch(&feed, &cache, &archiver).await.unwrap();
 
         mock.assert();
-        assert_eq!(num_found, 243);
+        assert_eq!(summary.num_trip_updates, 243);
+        assert_eq!(summary.timestamp, Some(1689266098));
+        assert_eq!(summary.status, 200);
+        let latest = cache.get(1).unwrap();
+        assert_eq!(latest.raw.len(), buffer_len);
+        assert_eq!(latest.message.header.timestamp, Some(1689266098));
+
+        // The blocking client archives the same way
+        let archived = archiver.clone();
+        tokio::task::spawn_blocking(move || fetch_sync(&feed, &FeedCache::new(), &archived))
+            .await
+            .unwrap()
+            .unwrap();
+        let payloads: Vec<_> = fs::read_dir(dir.join("1")).unwrap().collect();
+        assert_eq!(payloads.len(), 2);
+        for payload in payloads {
+            assert_eq!(fs::read(payload.unwrap().path()).unwrap().len(), buffer_len);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetch_errors() {
+        let server = mockito::Server::new_async().await;
+        server.mock("GET", "/missing").with_status(404).create();
+        server
+            .mock("GET", "/garbage")
+            .with_status(200)
+            .with_body(vec![0xff; 4])
+            .create();
+
+        let feed = |url: String| Feed {
+            frequency: 5,
+            ..Feed::test(url)
+        };
+        let cache = FeedCache::new();
+
+        let e = fetch(
+            &feed(format!("{}/missing", server.url())),
+            &cache,
+            &Archiver::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(e.kind, FetchErrorKind::Http);
+        assert_eq!(e.status, Some(404));
+
+        let e = fetch(
+            &feed(format!("{}/garbage", server.url())),
+            &cache,
+            &Archiver::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(e.kind, FetchErrorKind::Decode);
+        assert_eq!(e.status, Some(200));
+
+        // Nothing listens on port 1
+        let e = fetch(
+            &feed("http://127.0.0.1:1/gtfs".to_string()),
+            &cache,
+            &Archiver::default(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(e.kind, FetchErrorKind::Connect);
+        assert_eq!(e.status, None);
+
+        // The blocking client reports the same errors
+        let url = format!("{}/missing", server.url());
+        let e = tokio::task::spawn_blocking(move || {
+            fetch_sync(&feed(url), &FeedCache::new(), &Archiver::default())
+        })
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(e.kind, FetchErrorKind::Http);
+        assert_eq!(e.status, Some(404));
+
+        // Neither client sends a header which is not valid
+        let mut bad_header = feed(format!("{}/missing", server.url()));
+        bad_header
+            .headers
+            .insert("x-api-key".to_string(), "key\n".to_string());
+        let e = fetch(&bad_header, &cache, &Archiver::default())
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind, FetchErrorKind::Request);
+        assert_eq!(e.status, None);
+        let e = tokio::task::spawn_blocking(move || {
+            fetch_sync(&bad_header, &FeedCache::new(), &Archiver::default())
+        })
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(e.kind, FetchErrorKind::Request);
+
+        assert!(cache.get(1).is_none());
+    }
+
+    #[test]
+    fn pacer() {
+        let feed = Feed {
+            frequency: 5,
+            ..Feed::test("http")
+        };
+        let pacer = Pacer::new(&feed);
+        let secs = Duration::from_secs;
+
+        assert_eq!(pacer.bounds(), (secs(5), secs(40)));
+        assert_eq!(pacer.next(Some(1)), secs(5));
+        assert_eq!(pacer.next(Some(1)), secs(10));
+        assert_eq!(pacer.next(None), secs(10));
+        assert_eq!(pacer.next(Some(1)), secs(20));
+        assert_eq!(pacer.next(Some(1)), secs(40));
+        assert_eq!(pacer.next(Some(1)), secs(40));
+        assert_eq!(pacer.next(Some(2)), secs(20));
+
+        // A frequency which cannot be backed off saturates instead of overflowing
+        let pacer = Pacer::new(&Feed {
+            frequency: u64::MAX,
+            ..feed
+        });
+        assert_eq!(pacer.bounds(), (secs(u64::MAX), Duration::MAX));
+        assert_eq!(pacer.next(Some(1)), secs(u64::MAX));
+        assert_eq!(pacer.next(Some(1)), Duration::MAX);
     }
 }
@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    fetcher::{FetchError, Summary},
+    models::Staleness,
+};
+
+/// Which rule of its `Staleness` a feed broke.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StaleReason {
+    /// The header timestamp is older than `max_header_age`.
+    HeaderAge,
+    /// There was no successful fetch for longer than `max_without_success`.
+    NoSuccess,
+}
+
+/// How the fetches of a feed have gone so far.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Health {
+    pub first_attempt: Option<SystemTime>,
+    pub last_attempt: Option<SystemTime>,
+    pub last_success: Option<SystemTime>,
+    /// The status of the last response, which a failed fetch may not have.
+    pub status: Option<u16>,
+    /// Why the last fetch failed, or `None` if it succeeded.
+    pub error: Option<FetchError>,
+    pub consecutive_failures: u32,
+    /// The `FeedHeader.timestamp` of the last message, in seconds since the Unix epoch.
+    pub header_timestamp: Option<u64>,
+    /// The rules the feed broke at its last fetch. The feed is stale unless this is empty.
+    pub stale: Vec<StaleReason>,
+}
+
+/// A feed which became stale or recovered at its last fetch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Stale(Vec<StaleReason>),
+    Recovered,
+}
+
+impl Health {
+    /// The rules of `staleness` which are broken at `now`.
+    fn stale_reasons(&self, staleness: &Staleness, now: SystemTime) -> Vec<StaleReason> {
+        let mut reasons = Vec::new();
+
+        let header_age = self.header_timestamp.map(|timestamp| {
+            let seconds = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            seconds.saturating_sub(timestamp)
+        });
+        if let (Some(max), Some(age)) = (staleness.max_header_age, header_age) {
+            if age > max {
+                reasons.push(StaleReason::HeaderAge);
+            }
+        }
+
+        // A feed which never succeeded is measured from its first attempt.
+        let since = self.last_success.or(self.first_attempt);
+        let without_success = since.map(|since| now.duration_since(since).unwrap_or_default());
+        if let (Some(max), Some(without)) = (staleness.max_without_success, without_success) {
+            if without > Duration::from_secs(max) {
+                reasons.push(StaleReason::NoSuccess);
+            }
+        }
+
+        reasons
+    }
+}
+
+/// Keeps the `Health` of each feed. The fetch tasks record their results and the API reads them.
+#[derive(Debug, Default)]
+pub struct FeedHealth {
+    feeds: RwLock<HashMap<usize, Health>>,
+}
+
+impl FeedHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: usize) -> Option<Health> {
+        self.feeds.read().unwrap().get(&id).cloned()
+    }
+
+    /// Record a fetch of a feed and check its `staleness`. Returns the transition if the feed
+    /// became stale or recovered.
+    pub fn record(
+        &self,
+        id: usize,
+        staleness: Option<&Staleness>,
+        result: &Result<Summary, FetchError>,
+    ) -> Option<Transition> {
+        let now = SystemTime::now();
+        let mut feeds = self.feeds.write().unwrap();
+        let health = feeds.entry(id).or_default();
+        health.first_attempt.get_or_insert(now);
+        health.last_attempt = Some(now);
+        match result {
+            Ok(summary) => {
+                health.last_success = Some(now);
+                health.status = Some(summary.status);
+                health.error = None;
+                health.consecutive_failures = 0;
+                health.header_timestamp = summary.timestamp;
+            }
+            Err(e) => {
+                health.status = e.status;
+                health.error = Some(e.clone());
+                health.consecutive_failures += 1;
+            }
+        }
+
+        let reasons = staleness
+            .map(|staleness| health.stale_reasons(staleness, now))
+            .unwrap_or_default();
+        let was_stale = !health.stale.is_empty();
+        health.stale = reasons.clone();
+        match (was_stale, reasons.is_empty()) {
+            (false, false) => Some(Transition::Stale(reasons)),
+            (true, true) => Some(Transition::Recovered),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&self, id: usize) {
+        self.feeds.write().unwrap().remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::fetcher::FetchErrorKind;
+
+    fn fetched(timestamp: Option<u64>) -> Result<Summary, FetchError> {
+        Ok(Summary {
+            num_trip_updates: 0,
+            timestamp,
+            status: 200,
+        })
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn header_age() {
+        let health = FeedHealth::new();
+        let staleness = Staleness {
+            max_header_age: Some(60),
+            max_without_success: None,
+        };
+
+        // The feed keeps returning a message from an hour ago
+        let old = Some(now() - 3600);
+        assert_eq!(
+            health.record(1, Some(&staleness), &fetched(old)),
+            Some(Transition::Stale(vec![StaleReason::HeaderAge]))
+        );
+        assert_eq!(health.record(1, Some(&staleness), &fetched(old)), None);
+        assert_eq!(health.get(1).unwrap().stale, [StaleReason::HeaderAge]);
+
+        // Without a timestamp the age is unknown, which does not count as stale
+        assert_eq!(
+            health.record(1, Some(&staleness), &fetched(None)),
+            Some(Transition::Recovered)
+        );
+        assert_eq!(
+            health.record(1, Some(&staleness), &fetched(old)),
+            Some(Transition::Stale(vec![StaleReason::HeaderAge]))
+        );
+        assert_eq!(
+            health.record(1, Some(&staleness), &fetched(Some(now()))),
+            Some(Transition::Recovered)
+        );
+        assert!(health.get(1).unwrap().stale.is_empty());
+
+        // A feed without rules is never stale
+        assert_eq!(health.record(2, None, &fetched(old)), None);
+    }
+
+    #[test]
+    fn without_success() {
+        let health = FeedHealth::new();
+        let staleness = Staleness {
+            max_header_age: None,
+            max_without_success: Some(0),
+        };
+        let failed = Err(FetchError {
+            kind: FetchErrorKind::Timeout,
+            status: None,
+            message: "timed out".to_string(),
+        });
+
+        assert_eq!(health.record(1, Some(&staleness), &fetched(None)), None);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            health.record(1, Some(&staleness), &failed),
+            Some(Transition::Stale(vec![StaleReason::NoSuccess]))
+        );
+        let feed = health.get(1).unwrap();
+        assert_eq!(feed.consecutive_failures, 1);
+        assert_eq!(feed.error.unwrap().kind, FetchErrorKind::Timeout);
+
+        assert_eq!(
+            health.record(1, Some(&staleness), &fetched(None)),
+            Some(Transition::Recovered)
+        );
+
+        // A feed which never succeeded counts from its first attempt
+        assert_eq!(health.record(2, Some(&staleness), &failed), None);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            health.record(2, Some(&staleness), &failed),
+            Some(Transition::Stale(vec![StaleReason::NoSuccess]))
+        );
+    }
+}
@@ -1,8 +1,13 @@
 pub mod api;
+pub mod archive;
+pub mod cache;
 pub mod fetcher;
+pub mod health;
 pub mod middleware;
 pub mod models;
 pub mod scheduler_interface;
+pub mod store;
+pub mod webhooks;
 
 // The deps module is an effort to re-implement my third-party dependencies as
 // a learning exercise. I do not plan to make this code public and my
@@ -2,7 +2,7 @@ use std::net::SocketAddr;
 use tokio::{net::TcpListener, runtime::Builder};
 use tracing::info;
 
-use app::{api, scheduler_interface::build};
+use app::{api, scheduler_interface::build, store};
 
 fn main() {
     // Initialize tracing subscriber for logging
@@ -12,6 +12,7 @@ fn main() {
     info!("Starting server on {}.", address);
 
     let interface = build();
+    let store = store::build().expect("Failed to open the feed store.");
 
     // We use a runtime::Builder to specify the number of threads and
     // their name.
@@ -27,7 +28,7 @@ fn main() {
 
     runtime.block_on(async {
         let listener = TcpListener::bind(address).await.unwrap();
-        let router = api::app(interface).into_make_service();
+        let router = api::app_with_store(interface, store).into_make_service();
         axum::serve(listener, router).await.unwrap();
     });
 }
@@ -4,7 +4,7 @@ use axum::{
     middleware::Next,
 };
 use std::convert::Infallible;
-use tracing::info;
+use tracing::{info, info_span, Instrument};
 
 // Middleware function to log requests
 pub async fn log_request(req: Request<Body>, next: Next) -> Result<Response<Body>, Infallible> {
@@ -12,8 +12,10 @@ pub async fn log_request(req: Request<Body>, next: Next) -> Result<Response<Body
     let uri = req.uri().clone();
     info!("Incoming request: {} {}", method, uri);
 
-    // Call the next middleware or handler
-    let response = next.run(req).await;
+    // Call the next middleware or handler. Tasks sent while handling the request are traced
+    // under its span.
+    let span = info_span!("request", %method, %uri);
+    let response = next.run(req).instrument(span).await;
 
     Ok(response)
 }
@@ -1,6 +1,19 @@
 use reqwest::header::{HeaderMap, HeaderName};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tulsa::{Execution, Outcome};
+
+use crate::{
+    cache::Latest,
+    fetcher::{transit::FeedMessage, FetchError},
+    health::{Health, StaleReason},
+};
+
+pub mod realtime;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Feed {
@@ -9,19 +22,44 @@ pub struct Feed {
     pub url: String,
     pub frequency: u64,
     pub headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staleness: Option<Staleness>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<Archive>,
 }
 
 impl Feed {
-    pub fn to_header_map(&self) -> HeaderMap {
-        let mut headers = HeaderMap::new();
-        for (key, value) in self.headers.iter() {
-            let new_key: HeaderName = key.parse().unwrap();
-            headers.insert(new_key, value.parse().unwrap());
+    pub fn to_header_map(&self) -> Result<HeaderMap, InvalidFeed> {
+        header_map(&self.headers)
+    }
+}
+
+#[cfg(test)]
+impl Feed {
+    /// A feed of `url`, which tests change as they need to.
+    pub fn test(url: impl ToString) -> Self {
+        Self {
+            id: 1,
+            name: "Test".to_string(),
+            url: url.to_string(),
+            frequency: 10,
+            headers: HashMap::new(),
+            staleness: None,
+            archive: None,
         }
-        headers
     }
 }
 
+fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap, InvalidFeed> {
+    let mut map = HeaderMap::new();
+    for (key, value) in headers.iter() {
+        let invalid = || InvalidFeed::Header(key.clone());
+        let name: HeaderName = key.parse().map_err(|_| invalid())?;
+        map.insert(name, value.parse().map_err(|_| invalid())?);
+    }
+    Ok(map)
+}
+
 /// This represents a [`Feed`] but without an ID, which are used in POST bodies.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct CreateFeed {
@@ -29,6 +67,243 @@ pub struct CreateFeed {
     pub url: String,
     pub frequency: u64,
     pub headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staleness: Option<Staleness>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<Archive>,
+}
+
+/// The longest time between two fetches of a feed, in seconds, which is a day.
+pub const MAX_FREQUENCY: u64 = 24 * 60 * 60;
+
+/// Used to indicate a [`CreateFeed`] cannot be scheduled.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidFeed {
+    /// The frequency is zero or greater than `MAX_FREQUENCY`.
+    Frequency(u64),
+    /// The header of this name, or its value, cannot be sent in a request.
+    Header(String),
+}
+
+impl fmt::Display for InvalidFeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidFeed::Frequency(frequency) => write!(
+                f,
+                "frequency {} is not between 1 and {} seconds",
+                frequency, MAX_FREQUENCY
+            ),
+            InvalidFeed::Header(name) => write!(f, "header {} is not valid", name),
+        }
+    }
+}
+
+impl std::error::Error for InvalidFeed {}
+
+impl CreateFeed {
+    /// Check what the scheduler cannot use, before the feed is stored or scheduled.
+    pub fn validate(&self) -> Result<(), InvalidFeed> {
+        if !(1..=MAX_FREQUENCY).contains(&self.frequency) {
+            return Err(InvalidFeed::Frequency(self.frequency));
+        }
+        header_map(&self.headers)?;
+        Ok(())
+    }
+}
+
+/// Keeps the payloads of a [`Feed`] as they were fetched, if the app has an archive directory.
+/// A limit which is not set does not remove anything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Archive {
+    /// Compress each payload with gzip.
+    #[serde(default)]
+    pub compress: bool,
+    /// Bytes the payloads of the feed may take up together, after which the oldest are removed.
+    pub max_bytes: Option<u64>,
+    /// Seconds a payload is kept.
+    pub max_age: Option<u64>,
+}
+
+/// When a [`Feed`] is stale, which is checked after each of its fetches. A rule which is not set
+/// is not checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Staleness {
+    /// Seconds the `FeedHeader.timestamp` of the last message may be behind the clock.
+    pub max_header_age: Option<u64>,
+    /// Seconds the feed may go without a successful fetch.
+    pub max_without_success: Option<u64>,
+}
+
+/// A single execution of the fetch task of a [`Feed`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Run {
+    /// Milliseconds since the Unix epoch.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+impl From<Execution> for Run {
+    fn from(execution: Execution) -> Self {
+        let outcome = match execution.outcome {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Panic => "panic",
+            Outcome::Skipped => "skipped",
+        };
+
+        Self {
+            started_at: execution
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            duration_ms: execution.duration.as_millis() as u64,
+            outcome: outcome.to_string(),
+            error: execution.error,
+        }
+    }
+}
+
+/// The most recent message of a [`Feed`], as served by `GET /feed/:id/latest`.
+#[derive(Serialize)]
+pub struct LatestFeed<'a> {
+    /// Milliseconds since the Unix epoch.
+    pub fetched_at: u64,
+    /// The `FeedHeader.timestamp`, in seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+    pub counts: EntityCounts,
+    pub message: &'a FeedMessage,
+}
+
+/// The number of entities of each type in a message.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityCounts {
+    pub trip_updates: usize,
+    pub vehicles: usize,
+    pub alerts: usize,
+}
+
+impl<'a> From<&'a Latest> for LatestFeed<'a> {
+    fn from(latest: &'a Latest) -> Self {
+        let mut counts = EntityCounts::default();
+        for entity in latest.message.entity.iter() {
+            counts.trip_updates += entity.trip_update.is_some() as usize;
+            counts.vehicles += entity.vehicle.is_some() as usize;
+            counts.alerts += entity.alert.is_some() as usize;
+        }
+
+        Self {
+            fetched_at: latest
+                .fetched_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            timestamp: latest.message.header.timestamp,
+            counts,
+            message: &latest.message,
+        }
+    }
+}
+
+/// Whether the fetches of a feed work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    /// The feed was not fetched yet.
+    Unknown,
+    Healthy,
+    /// The last fetch failed.
+    Failing,
+    /// The last fetch succeeded, but the feed broke a rule of its [`Staleness`].
+    Stale,
+}
+
+/// The fetch health of a [`Feed`], as served by `GET /feed/:id/status`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeedStatus {
+    pub id: usize,
+    pub state: HealthState,
+    /// Milliseconds since the Unix epoch.
+    pub last_attempt_at: Option<u64>,
+    /// Milliseconds since the Unix epoch.
+    pub last_success_at: Option<u64>,
+    /// The HTTP status of the last response.
+    pub status_code: Option<u16>,
+    /// Why the last fetch failed.
+    pub error: Option<FetchError>,
+    pub consecutive_failures: u32,
+    /// The `FeedHeader.timestamp` of the last message, in seconds since the Unix epoch.
+    pub header_timestamp: Option<u64>,
+    /// The rules of its [`Staleness`] the feed broke at its last fetch.
+    pub stale: Vec<StaleReason>,
+}
+
+impl FeedStatus {
+    pub fn new(id: usize, health: Option<Health>) -> Self {
+        let health = health.unwrap_or_default();
+        Self {
+            id,
+            state: state(&health),
+            last_attempt_at: health.last_attempt.map(millis),
+            last_success_at: health.last_success.map(millis),
+            status_code: health.status,
+            error: health.error,
+            consecutive_failures: health.consecutive_failures,
+            header_timestamp: health.header_timestamp,
+            stale: health.stale,
+        }
+    }
+}
+
+/// A [`Feed`] with a summary of its health, as listed by `GET /feed`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeedSummary {
+    #[serde(flatten)]
+    pub feed: Feed,
+    pub health: HealthSummary,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthSummary {
+    pub state: HealthState,
+    /// Milliseconds since the Unix epoch.
+    pub last_success_at: Option<u64>,
+    pub consecutive_failures: u32,
+}
+
+impl FeedSummary {
+    pub fn new(feed: Feed, health: Option<Health>) -> Self {
+        let health = health.unwrap_or_default();
+        Self {
+            feed,
+            health: HealthSummary {
+                state: state(&health),
+                last_success_at: health.last_success.map(millis),
+                consecutive_failures: health.consecutive_failures,
+            },
+        }
+    }
+}
+
+fn state(health: &Health) -> HealthState {
+    if health.last_attempt.is_none() {
+        HealthState::Unknown
+    } else if health.error.is_some() {
+        HealthState::Failing
+    } else if !health.stale.is_empty() {
+        HealthState::Stale
+    } else {
+        HealthState::Healthy
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
 }
 
 #[derive(Serialize)]
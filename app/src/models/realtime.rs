@@ -0,0 +1,568 @@
+use serde::{Deserialize, Serialize};
+
+use crate::fetcher::transit::{self, FeedMessage};
+
+/// The filters of `GET /feed/:id/trip_updates`. A trip matches when it has every given value.
+#[derive(Debug, Default, Deserialize)]
+pub struct TripQuery {
+    pub route_id: Option<String>,
+    pub trip_id: Option<String>,
+    pub direction_id: Option<u32>,
+    /// In the `YYYYMMDD` format of GTFS.
+    pub start_date: Option<String>,
+}
+
+impl TripQuery {
+    pub fn matches(&self, trip: &transit::TripDescriptor) -> bool {
+        fn matches<T: PartialEq>(wanted: &Option<T>, value: &Option<T>) -> bool {
+            wanted.is_none() || wanted == value
+        }
+
+        matches(&self.route_id, &trip.route_id)
+            && matches(&self.trip_id, &trip.trip_id)
+            && matches(&self.direction_id, &trip.direction_id)
+            && matches(&self.start_date, &trip.start_date)
+    }
+}
+
+/// A `TripUpdate` entity of a feed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TripUpdate {
+    pub entity_id: String,
+    pub trip: Trip,
+    pub vehicle: Option<Vehicle>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+    pub delay: Option<i32>,
+    pub stop_time_updates: Vec<StopTimeUpdate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Trip {
+    pub trip_id: Option<String>,
+    pub route_id: Option<String>,
+    pub direction_id: Option<u32>,
+    pub start_time: Option<String>,
+    pub start_date: Option<String>,
+    pub schedule_relationship: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Vehicle {
+    pub id: Option<String>,
+    pub label: Option<String>,
+    pub license_plate: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StopTimeUpdate {
+    pub stop_sequence: Option<u32>,
+    pub stop_id: Option<String>,
+    pub arrival: Option<StopTimeEvent>,
+    pub departure: Option<StopTimeEvent>,
+    pub schedule_relationship: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StopTimeEvent {
+    /// Seconds, where a positive delay means the vehicle is late.
+    pub delay: Option<i32>,
+    /// Seconds since the Unix epoch.
+    pub time: Option<i64>,
+}
+
+impl TripUpdate {
+    pub fn new(entity_id: &str, update: &transit::TripUpdate) -> Self {
+        Self {
+            entity_id: entity_id.to_string(),
+            trip: Trip::from(&update.trip),
+            vehicle: update.vehicle.as_ref().map(Vehicle::from),
+            timestamp: update.timestamp,
+            delay: update.delay,
+            stop_time_updates: update
+                .stop_time_update
+                .iter()
+                .map(StopTimeUpdate::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<&transit::TripDescriptor> for Trip {
+    fn from(trip: &transit::TripDescriptor) -> Self {
+        Self {
+            trip_id: trip.trip_id.clone(),
+            route_id: trip.route_id.clone(),
+            direction_id: trip.direction_id,
+            start_time: trip.start_time.clone(),
+            start_date: trip.start_date.clone(),
+            // An unset relationship reads as the default, SCHEDULED.
+            schedule_relationship: trip.schedule_relationship().as_str_name().to_string(),
+        }
+    }
+}
+
+impl From<&transit::VehicleDescriptor> for Vehicle {
+    fn from(vehicle: &transit::VehicleDescriptor) -> Self {
+        Self {
+            id: vehicle.id.clone(),
+            label: vehicle.label.clone(),
+            license_plate: vehicle.license_plate.clone(),
+        }
+    }
+}
+
+impl From<&transit::trip_update::StopTimeUpdate> for StopTimeUpdate {
+    fn from(update: &transit::trip_update::StopTimeUpdate) -> Self {
+        Self {
+            stop_sequence: update.stop_sequence,
+            stop_id: update.stop_id.clone(),
+            arrival: update.arrival.as_ref().map(StopTimeEvent::from),
+            departure: update.departure.as_ref().map(StopTimeEvent::from),
+            schedule_relationship: update.schedule_relationship().as_str_name().to_string(),
+        }
+    }
+}
+
+impl From<&transit::trip_update::StopTimeEvent> for StopTimeEvent {
+    fn from(event: &transit::trip_update::StopTimeEvent) -> Self {
+        Self {
+            delay: event.delay,
+            time: event.time,
+        }
+    }
+}
+
+/// The trip updates of `message` which match `query`, in the order of the feed.
+pub fn trip_updates(message: &FeedMessage, query: &TripQuery) -> Vec<TripUpdate> {
+    message
+        .entity
+        .iter()
+        .filter_map(|entity| {
+            let update = entity.trip_update.as_ref()?;
+            query
+                .matches(&update.trip)
+                .then(|| TripUpdate::new(&entity.id, update))
+        })
+        .collect()
+}
+
+/// The filters of `GET /stops/:stop_id/arrivals`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ArrivalQuery {
+    /// Only arrivals at or after this time, in seconds since the Unix epoch. The default is now.
+    pub after: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+/// A predicted arrival of a trip at a stop.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Arrival {
+    pub feed_id: usize,
+    pub route_id: Option<String>,
+    pub trip_id: Option<String>,
+    /// GTFS-Realtime has no headsign, so this is the label of the vehicle, which feeds set to what
+    /// riders see on it.
+    pub headsign: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    /// Seconds, where a positive delay means the vehicle is late.
+    pub delay: Option<i32>,
+}
+
+/// The arrivals at `stop_id` in the trip updates of `message`, from `after` on, in the order of
+/// the feed. Canceled trips and skipped stops are left out. A stop which has no arrival time, like
+/// the first stop of a trip or an arrival with only a delay, arrives at its departure.
+pub fn arrivals(feed_id: usize, message: &FeedMessage, stop_id: &str, after: i64) -> Vec<Arrival> {
+    use transit::{
+        trip_descriptor::ScheduleRelationship as TripRelationship,
+        trip_update::stop_time_update::ScheduleRelationship as StopRelationship,
+    };
+
+    let mut arrivals = Vec::new();
+    for update in message.entity.iter().filter_map(|e| e.trip_update.as_ref()) {
+        if update.trip.schedule_relationship() == TripRelationship::Canceled {
+            continue;
+        }
+        for stop in update.stop_time_update.iter() {
+            if stop.stop_id.as_deref() != Some(stop_id)
+                || stop.schedule_relationship() == StopRelationship::Skipped
+            {
+                continue;
+            }
+            let arrival = stop.arrival.as_ref().filter(|event| event.time.is_some());
+            let event = match arrival.or(stop.departure.as_ref()) {
+                Some(event) => event,
+                None => continue,
+            };
+            match event.time {
+                Some(time) if time >= after => arrivals.push(Arrival {
+                    feed_id,
+                    route_id: update.trip.route_id.clone(),
+                    trip_id: update.trip.trip_id.clone(),
+                    headsign: update.vehicle.as_ref().and_then(|v| v.label.clone()),
+                    time,
+                    delay: event.delay,
+                }),
+                _ => {}
+            }
+        }
+    }
+    arrivals
+}
+
+/// The filters of `GET /feed/:id/vehicles`.
+#[derive(Debug, Default, Deserialize)]
+pub struct VehicleQuery {
+    pub route_id: Option<String>,
+    /// Only vehicles with a position in this box.
+    pub bbox: Option<BoundingBox>,
+}
+
+/// An area given as `min_lon,min_lat,max_lon,max_lat`, the order of a GeoJSON `bbox`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct BoundingBox {
+    pub min_lon: f32,
+    pub min_lat: f32,
+    pub max_lon: f32,
+    pub max_lat: f32,
+}
+
+impl BoundingBox {
+    pub fn contains(&self, position: &transit::Position) -> bool {
+        (self.min_lon..=self.max_lon).contains(&position.longitude)
+            && (self.min_lat..=self.max_lat).contains(&position.latitude)
+    }
+}
+
+impl TryFrom<String> for BoundingBox {
+    type Error = String;
+
+    fn try_from(bbox: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("expected min_lon,min_lat,max_lon,max_lat, got {:?}", bbox);
+        let values = bbox
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        match values[..] {
+            [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+                Ok(Self {
+                    min_lon,
+                    min_lat,
+                    max_lon,
+                    max_lat,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A GeoJSON `FeatureCollection` of the vehicles of a feed.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+/// A GeoJSON `Feature` for a single vehicle. A vehicle which does not report a position has no
+/// geometry.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    /// The id of the entity.
+    pub id: String,
+    pub geometry: Option<Point>,
+    pub properties: VehicleProperties,
+}
+
+/// A GeoJSON `Point`, at `[longitude, latitude]`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub struct Point {
+    pub coordinates: [f32; 2],
+}
+
+/// What a `VehiclePosition` reports besides where the vehicle is. The enums are given by name,
+/// and are `None` if the feed leaves them out.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VehicleProperties {
+    pub vehicle: Option<Vehicle>,
+    pub trip: Option<Trip>,
+    /// Degrees clockwise from true north.
+    pub bearing: Option<f32>,
+    /// Meters per second.
+    pub speed: Option<f32>,
+    pub stop_id: Option<String>,
+    pub current_stop_sequence: Option<u32>,
+    pub current_status: Option<String>,
+    pub congestion_level: Option<String>,
+    pub occupancy_status: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+}
+
+impl Feature {
+    pub fn new(entity_id: &str, position: &transit::VehiclePosition) -> Self {
+        let point = position.position.as_ref();
+        Self {
+            id: entity_id.to_string(),
+            geometry: point.map(|point| Point {
+                coordinates: [point.longitude, point.latitude],
+            }),
+            properties: VehicleProperties {
+                vehicle: position.vehicle.as_ref().map(Vehicle::from),
+                trip: position.trip.as_ref().map(Trip::from),
+                bearing: point.and_then(|point| point.bearing),
+                speed: point.and_then(|point| point.speed),
+                stop_id: position.stop_id.clone(),
+                current_stop_sequence: position.current_stop_sequence,
+                current_status: position
+                    .current_status
+                    .map(|_| position.current_status().as_str_name().to_string()),
+                congestion_level: position
+                    .congestion_level
+                    .map(|_| position.congestion_level().as_str_name().to_string()),
+                occupancy_status: position
+                    .occupancy_status
+                    .map(|_| position.occupancy_status().as_str_name().to_string()),
+                timestamp: position.timestamp,
+            },
+        }
+    }
+}
+
+/// The vehicles of `message` which match `query`, in the order of the feed. A vehicle without a
+/// position is left out when there is a bounding box.
+pub fn vehicles(message: &FeedMessage, query: &VehicleQuery) -> FeatureCollection {
+    let features = message
+        .entity
+        .iter()
+        .filter_map(|entity| {
+            let vehicle = entity.vehicle.as_ref()?;
+            let route_id = vehicle
+                .trip
+                .as_ref()
+                .and_then(|trip| trip.route_id.as_ref());
+            if query.route_id.is_some() && query.route_id.as_ref() != route_id {
+                return None;
+            }
+            if let Some(bbox) = query.bbox {
+                if !vehicle.position.as_ref().is_some_and(|p| bbox.contains(p)) {
+                    return None;
+                }
+            }
+            Some(Feature::new(&entity.id, vehicle))
+        })
+        .collect();
+    FeatureCollection { features }
+}
+
+/// The filters of `GET /feed/:id/alerts`. The causes, effects and severities are given by name,
+/// e.g. `cause=CONSTRUCTION`.
+#[derive(Debug, Default, Deserialize)]
+pub struct AlertQuery {
+    /// Only alerts which are active now.
+    #[serde(default)]
+    pub active: bool,
+    /// Only alerts which are active at this time, in seconds since the Unix epoch.
+    pub active_at: Option<u64>,
+    pub route_id: Option<String>,
+    pub stop_id: Option<String>,
+    pub trip_id: Option<String>,
+    pub agency_id: Option<String>,
+    pub cause: Option<String>,
+    pub effect: Option<String>,
+    pub severity: Option<String>,
+    /// The language of the text, which takes precedence over `Accept-Language`.
+    pub lang: Option<String>,
+}
+
+impl AlertQuery {
+    /// Returns `true` if `alert` is active at `now`, when asked for, and informs about every
+    /// route, stop, trip and agency of the query.
+    pub fn matches(&self, alert: &transit::Alert, now: u64) -> bool {
+        fn named(wanted: &Option<String>, name: &str) -> bool {
+            wanted
+                .as_ref()
+                .is_none_or(|wanted| wanted.eq_ignore_ascii_case(name))
+        }
+        fn informed<F>(wanted: &Option<String>, alert: &transit::Alert, get: F) -> bool
+        where
+            F: Fn(&transit::EntitySelector) -> Option<&String>,
+        {
+            wanted.as_ref().is_none_or(|wanted| {
+                alert
+                    .informed_entity
+                    .iter()
+                    .any(|entity| get(entity) == Some(wanted))
+            })
+        }
+
+        let at = self.active_at.or(self.active.then_some(now));
+        at.is_none_or(|at| is_active(alert, at))
+            && informed(&self.route_id, alert, |entity| {
+                let trip_route = || entity.trip.as_ref()?.route_id.as_ref();
+                entity.route_id.as_ref().or_else(trip_route)
+            })
+            && informed(&self.stop_id, alert, |entity| entity.stop_id.as_ref())
+            && informed(&self.trip_id, alert, |entity| {
+                entity.trip.as_ref()?.trip_id.as_ref()
+            })
+            && informed(&self.agency_id, alert, |entity| entity.agency_id.as_ref())
+            && named(&self.cause, alert.cause().as_str_name())
+            && named(&self.effect, alert.effect().as_str_name())
+            && named(&self.severity, alert.severity_level().as_str_name())
+    }
+}
+
+/// An alert without an active period is always active. Otherwise `at` has to be within one of
+/// them, where a period without a start or end is open on that side.
+fn is_active(alert: &transit::Alert, at: u64) -> bool {
+    alert.active_period.is_empty()
+        || alert.active_period.iter().any(|period| {
+            period.start.is_none_or(|start| start <= at) && period.end.is_none_or(|end| at <= end)
+        })
+}
+
+/// An `Alert` entity of a feed, with its text in a single language.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Alert {
+    pub entity_id: String,
+    pub active_periods: Vec<ActivePeriod>,
+    pub informed_entities: Vec<InformedEntity>,
+    pub cause: String,
+    pub effect: String,
+    pub severity_level: String,
+    pub header_text: Option<String>,
+    pub description_text: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Seconds since the Unix epoch, where a missing side is open.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActivePeriod {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InformedEntity {
+    pub agency_id: Option<String>,
+    pub route_id: Option<String>,
+    pub route_type: Option<i32>,
+    pub trip: Option<Trip>,
+    pub stop_id: Option<String>,
+    pub direction_id: Option<u32>,
+}
+
+impl Alert {
+    /// The alert with its text in the first of `languages` it has.
+    pub fn new(entity_id: &str, alert: &transit::Alert, languages: &[String]) -> Self {
+        let text = |text: &Option<transit::TranslatedString>| {
+            text.as_ref().and_then(|text| translate(text, languages))
+        };
+
+        Self {
+            entity_id: entity_id.to_string(),
+            active_periods: alert
+                .active_period
+                .iter()
+                .map(|period| ActivePeriod {
+                    start: period.start,
+                    end: period.end,
+                })
+                .collect(),
+            informed_entities: alert
+                .informed_entity
+                .iter()
+                .map(|entity| InformedEntity {
+                    agency_id: entity.agency_id.clone(),
+                    route_id: entity.route_id.clone(),
+                    route_type: entity.route_type,
+                    trip: entity.trip.as_ref().map(Trip::from),
+                    stop_id: entity.stop_id.clone(),
+                    direction_id: entity.direction_id,
+                })
+                .collect(),
+            cause: alert.cause().as_str_name().to_string(),
+            effect: alert.effect().as_str_name().to_string(),
+            severity_level: alert.severity_level().as_str_name().to_string(),
+            header_text: text(&alert.header_text),
+            description_text: text(&alert.description_text),
+            url: text(&alert.url),
+        }
+    }
+}
+
+/// The languages of an `Accept-Language` header, most preferred first. The wildcard and the
+/// languages with a weight of zero are left out, since any text will do once nothing matches.
+pub fn accepted_languages(accept_language: &str) -> Vec<String> {
+    let mut languages: Vec<(f32, String)> = accept_language
+        .split(',')
+        .filter_map(|language| {
+            let mut parts = language.split(';').map(str::trim);
+            let tag = parts.next().filter(|tag| !tag.is_empty() && *tag != "*")?;
+            let weight = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            (weight > 0.0).then(|| (weight, tag.to_string()))
+        })
+        .collect();
+    // The sort is stable, so languages of the same weight keep their order.
+    languages.sort_by(|a, b| b.0.total_cmp(&a.0));
+    languages.into_iter().map(|(_, tag)| tag).collect()
+}
+
+/// The translation in the first of `languages` which `text` has. A language also matches a
+/// translation which only shares its primary tag, so `en-US` finds `en`. Without a match, this is
+/// the untranslated text, or else the first translation.
+pub fn translate(text: &transit::TranslatedString, languages: &[String]) -> Option<String> {
+    fn primary(tag: &str) -> &str {
+        tag.split('-').next().unwrap_or(tag)
+    }
+
+    let translations = &text.translation;
+    let found = languages.iter().find_map(|language| {
+        let tagged = || {
+            translations
+                .iter()
+                .filter_map(|t| Some((t.language.as_deref()?, t)))
+        };
+        tagged()
+            .find(|(tag, _)| tag.eq_ignore_ascii_case(language))
+            .or_else(|| {
+                tagged().find(|(tag, _)| primary(tag).eq_ignore_ascii_case(primary(language)))
+            })
+            .map(|(_, t)| t)
+    });
+    found
+        .or_else(|| {
+            translations
+                .iter()
+                .find(|t| t.language.as_deref().unwrap_or_default().is_empty())
+        })
+        .or_else(|| translations.first())
+        .map(|t| t.text.clone())
+}
+
+/// The alerts of `message` which match `query` at `now`, in the order of the feed.
+pub fn alerts(
+    message: &FeedMessage,
+    query: &AlertQuery,
+    languages: &[String],
+    now: u64,
+) -> Vec<Alert> {
+    message
+        .entity
+        .iter()
+        .filter_map(|entity| {
+            let alert = entity.alert.as_ref()?;
+            query
+                .matches(alert, now)
+                .then(|| Alert::new(&entity.id, alert, languages))
+        })
+        .collect()
+}
@@ -1,35 +1,139 @@
 use std::{
+    env, fmt, fs,
     marker::PhantomData,
-    sync::{
-        mpsc::{self, SendError, Sender},
-        Arc,
-    },
+    sync::{mpsc::Sender, Arc},
     time::Duration,
 };
-use tulsa::{AsyncTask, Scheduler, SyncTask, Task};
+use tracing::{warn, Span};
+use tulsa::{
+    channel, AdmissionError, AsyncTask, BoundsError, Execution, FileLease, InMemoryMetrics, Limits,
+    Operation, Overlap, Scheduler, SchedulerHandle, Snapshot, SyncTask, Task, TaskSender,
+};
 
 use crate::{
-    fetcher::{fetch_sync, recurring_fetch},
+    archive::{self, Archiver},
+    cache::{FeedCache, Latest},
+    fetcher::{fetch, fetch_sync, FetchError, Pacer, Summary},
+    health::{FeedHealth, Health},
     models::Feed,
+    webhooks::{self, Event, Webhooks},
 };
 
 /// Used to indicate an action by a `ToScheduler` was unsuccessful.
-pub struct AppSendError;
+#[derive(Debug)]
+pub enum AppSendError {
+    /// The scheduler did not accept the task of the feed.
+    Rejected(AdmissionError),
+    /// The frequency of the feed cannot pace its fetches.
+    Bounds(BoundsError),
+}
+
+impl fmt::Display for AppSendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppSendError::Rejected(e) => write!(f, "{}", e),
+            AppSendError::Bounds(e) => write!(f, "invalid frequency: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppSendError {}
+
+impl From<BoundsError> for AppSendError {
+    fn from(e: BoundsError) -> Self {
+        AppSendError::Bounds(e)
+    }
+}
+
+/// The scheduler listens for `tulsactl` commands on this socket when the variable is set.
+const CONTROL_SOCKET_VAR: &str = "TULSA_CONTROL_SOCKET";
+
+/// The number of independent scheduler loops to spread the feeds across.
+const SHARDS_VAR: &str = "TULSA_SHARDS";
+
+/// Replicas which share a lease file on this path take turns polling the feeds.
+const LEASE_FILE_VAR: &str = "TULSA_LEASE_FILE";
+
+/// How long the lease outlives a replica which stops renewing it.
+const LEASE_TTL: Duration = Duration::from_secs(30);
+
+/// A snapshot from `GET /snapshot` of another process, whose feeds are resumed on startup.
+const SNAPSHOT_FILE_VAR: &str = "TULSA_SNAPSHOT_FILE";
 
 pub fn build() -> Arc<impl ToScheduler + Send + Sync + 'static> {
+    let snapshot = read_snapshot();
+    let feeds = snapshot.as_ref().map(restored_feeds).unwrap_or_default();
+
     #[cfg(feature = "async_mode")]
     {
-        let (sender, receiver) = mpsc::channel();
-        Scheduler::<AsyncTask>::new(receiver).run();
-        Arc::new(SchedulerInterface::new(sender))
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let (sender, receiver) = channel(Limits::default());
+        let sender = sender.metrics(metrics.clone());
+        let scheduler = Scheduler::<AsyncTask>::new(receiver).metrics(metrics.clone());
+        let handle = configure(scheduler, snapshot).run();
+        Arc::new(
+            SchedulerInterface::new(sender, handle)
+                .with_metrics(metrics)
+                .with_webhooks(Arc::new(webhooks::build()))
+                .with_archiver(Arc::new(archive::build()))
+                .with_restored(feeds),
+        )
     }
 
     #[cfg(not(feature = "async_mode"))]
     {
-        let (sender, receiver) = mpsc::channel();
-        Scheduler::<SyncTask>::new(receiver).run();
-        Arc::new(SchedulerInterface::new(sender))
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let (sender, receiver) = channel(Limits::default());
+        let sender = sender.metrics(metrics.clone());
+        let scheduler = Scheduler::<SyncTask>::new(receiver).metrics(metrics.clone());
+        let handle = configure(scheduler, snapshot).run();
+        Arc::new(
+            SchedulerInterface::new(sender, handle)
+                .with_metrics(metrics)
+                .with_webhooks(Arc::new(webhooks::build()))
+                .with_archiver(Arc::new(archive::build()))
+                .with_restored(feeds),
+        )
+    }
+}
+
+fn read_snapshot() -> Option<Snapshot> {
+    let path = env::var(SNAPSHOT_FILE_VAR).ok()?;
+    let snapshot = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()));
+    match snapshot {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            warn!("Cannot restore snapshot {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// The feeds which the fetch tasks of `snapshot` were described with.
+fn restored_feeds(snapshot: &Snapshot) -> Vec<Feed> {
+    snapshot
+        .tasks
+        .iter()
+        .filter_map(|task| serde_json::from_value(task.spec.clone()?).ok())
+        .collect()
+}
+
+fn configure<T>(mut scheduler: Scheduler<T>, snapshot: Option<Snapshot>) -> Scheduler<T> {
+    if let Ok(path) = env::var(CONTROL_SOCKET_VAR) {
+        scheduler = scheduler.control_socket(path);
+    }
+    if let Some(shards) = env::var(SHARDS_VAR).ok().and_then(|s| s.parse().ok()) {
+        scheduler = scheduler.shards(shards);
+    }
+    if let Ok(path) = env::var(LEASE_FILE_VAR) {
+        scheduler = scheduler.lease(FileLease::new(path, LEASE_TTL));
+    }
+    if let Some(snapshot) = snapshot {
+        scheduler = scheduler.restore(snapshot);
     }
+    scheduler
 }
 
 /// An interface to send a `Task`. This allows clients to mock a `Sender` for unit tests.
@@ -37,14 +141,23 @@ pub trait TaskSend<T>
 where
     T: Task,
 {
-    fn send(&self, task: T) -> Result<(), SendError<T>>;
+    fn send(&self, task: T) -> Result<(), AdmissionError>;
 }
 
 impl<T> TaskSend<T> for Sender<T>
 where
     T: Task,
 {
-    fn send(&self, task: T) -> Result<(), SendError<T>> {
+    fn send(&self, task: T) -> Result<(), AdmissionError> {
+        self.send(task).map_err(|_| AdmissionError::Disconnected)
+    }
+}
+
+impl<T> TaskSend<T> for TaskSender<T>
+where
+    T: Task,
+{
+    fn send(&self, task: T) -> Result<(), AdmissionError> {
         self.send(task)
     }
 }
@@ -54,6 +167,19 @@ pub trait ToScheduler {
     fn create(&self, feed: Feed) -> Result<(), AppSendError>;
     fn update(&self, feed: Feed) -> Result<(), AppSendError>;
     fn delete(&self, feed: Feed) -> Result<(), AppSendError>;
+    /// Fetch a feed right away, without changing its schedule.
+    fn trigger(&self, id: usize) -> Result<(), AppSendError>;
+    fn history(&self, id: usize) -> Vec<Execution>;
+    /// The most recent message fetched for a feed.
+    fn latest(&self, id: usize) -> Option<Arc<Latest>>;
+    /// How the fetches of a feed have gone, or `None` before its first fetch.
+    fn health(&self, id: usize) -> Option<Health>;
+    /// The scheduler metrics in the Prometheus text format.
+    fn metrics(&self) -> String;
+    /// The schedule of every feed, which another process can be started from.
+    fn snapshot(&self) -> Snapshot;
+    /// The feeds of the snapshot this process was started from, which still have to be created.
+    fn restored(&self) -> Vec<Feed>;
 }
 
 pub struct SchedulerInterface<R, T>
@@ -62,6 +188,10 @@ where
     T: Task,
 {
     sender: R,
+    handle: SchedulerHandle,
+    metrics: Arc<InMemoryMetrics>,
+    fetched: Fetched,
+    restored: Vec<Feed>,
     _marker: PhantomData<T>,
 }
 
@@ -70,12 +200,126 @@ where
     R: TaskSend<T> + Send + 'static,
     T: Task,
 {
-    pub fn new(sender: R) -> Self {
+    pub fn new(sender: R, handle: SchedulerHandle) -> Self {
         Self {
             sender,
+            handle,
+            metrics: Arc::new(InMemoryMetrics::new()),
+            fetched: Fetched::default(),
+            restored: Vec::new(),
             _marker: PhantomData,
         }
     }
+
+    /// Serve `metrics`, which should be the ones the scheduler reports to.
+    pub fn with_metrics(mut self, metrics: Arc<InMemoryMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Keep the messages of the fetch tasks in `cache`.
+    pub fn with_cache(mut self, cache: Arc<FeedCache>) -> Self {
+        self.fetched.cache = cache;
+        self
+    }
+
+    /// Record how the fetch tasks go in `health`.
+    pub fn with_health(mut self, health: Arc<FeedHealth>) -> Self {
+        self.fetched.health = health;
+        self
+    }
+
+    /// Post to `webhooks` when a feed becomes stale or recovers.
+    pub fn with_webhooks(mut self, webhooks: Arc<Webhooks>) -> Self {
+        self.fetched.webhooks = webhooks;
+        self
+    }
+
+    /// Archive the payloads of the fetch tasks with `archiver`.
+    pub fn with_archiver(mut self, archiver: Arc<Archiver>) -> Self {
+        self.fetched.archiver = archiver;
+        self
+    }
+
+    /// Resume `feeds`, which were taken from a snapshot given to the scheduler.
+    pub fn with_restored(mut self, feeds: Vec<Feed>) -> Self {
+        self.restored = feeds;
+        self
+    }
+}
+
+/// Where the fetch tasks keep what they found, which the API reads.
+#[derive(Clone, Default)]
+struct Fetched {
+    cache: Arc<FeedCache>,
+    health: Arc<FeedHealth>,
+    webhooks: Arc<Webhooks>,
+    archiver: Arc<Archiver>,
+}
+
+impl Fetched {
+    /// Record a fetch of `feed` in its health, and tell the webhooks if that made the feed stale
+    /// or recover.
+    fn record(&self, feed: &Feed, result: &Result<Summary, FetchError>) {
+        let transition = self.health.record(feed.id, feed.staleness.as_ref(), result);
+        if let Some(transition) = transition {
+            warn!("Feed {} is now {:?}", feed.id, transition);
+            self.webhooks.send(Event::new(feed, transition));
+        }
+    }
+
+    fn remove(&self, id: usize) {
+        self.cache.remove(id);
+        self.health.remove(id);
+    }
+}
+
+/// A task which fetches `feed`, adapting its frequency to how often the feed changes. A fetch
+/// which is still running when the next one is due makes it skip, since a queued fetch would
+/// only return what was just fetched. Every fetch is recorded in the health of the feed, and a
+/// failed one keeps the current delay. Its fetches are traced under the request which sent it,
+/// and it is described by the feed so that it can be restored from a snapshot.
+fn sync_fetch_task(feed: Feed, fetched: Fetched) -> Result<SyncTask, BoundsError> {
+    let pacer = Pacer::new(&feed);
+    let (min, max) = pacer.bounds();
+    let name = feed.name.clone();
+    let spec = serde_json::json!(&feed);
+    SyncTask::new(feed.id, min, move || {
+        let result = fetch_sync(&feed, &fetched.cache, &fetched.archiver);
+        fetched.record(&feed, &result);
+        result.map(|summary| pacer.next(summary.timestamp))
+    })
+    .adaptive(min, max)
+    .map(|task| {
+        task.overlap(Overlap::Skip)
+            .tag("feed", &name)
+            .parent_span(Span::current())
+            .describe(spec)
+    })
+}
+
+fn async_fetch_task(feed: Feed, fetched: Fetched) -> Result<AsyncTask, BoundsError> {
+    let pacer = Arc::new(Pacer::new(&feed));
+    let (min, max) = pacer.bounds();
+    let name = feed.name.clone();
+    let spec = serde_json::json!(&feed);
+    AsyncTask::recurring(feed.id, min, move || {
+        let feed = feed.clone();
+        let pacer = pacer.clone();
+        let fetched = fetched.clone();
+        async move {
+            let result = fetch(&feed, &fetched.cache, &fetched.archiver).await;
+            fetched.record(&feed, &result);
+            result.map(|summary| pacer.next(summary.timestamp))
+        }
+    })
+    .adaptive(min, max)
+    .map(|task| {
+        task.overlap(Overlap::Skip)
+            .tag("feed", &name)
+            .parent_span(Span::current())
+            .describe(spec)
+    })
 }
 
 impl<R> ToScheduler for SchedulerInterface<R, SyncTask>
@@ -83,22 +327,52 @@ where
     R: TaskSend<SyncTask> + Send + 'static,
 {
     fn create(&self, feed: Feed) -> Result<(), AppSendError> {
-        let action = SyncTask::new(feed.id, Duration::from_secs(feed.frequency), move || {
-            fetch_sync(&feed);
-        });
-        self.sender.send(action).map_err(|_| AppSendError)
+        let action = sync_fetch_task(feed, self.fetched.clone())?;
+        self.sender.send(action).map_err(AppSendError::Rejected)
     }
 
     fn update(&self, feed: Feed) -> Result<(), AppSendError> {
-        let action = SyncTask::update(feed.id, Duration::from_secs(feed.frequency), move || {
-            fetch_sync(&feed);
-        });
-        self.sender.send(action).map_err(|_| AppSendError)
+        let action = SyncTask {
+            op: Operation::Update,
+            ..sync_fetch_task(feed, self.fetched.clone())?
+        };
+        self.sender.send(action).map_err(AppSendError::Rejected)
     }
 
     fn delete(&self, feed: Feed) -> Result<(), AppSendError> {
         let action = SyncTask::stop(feed.id);
-        self.sender.send(action).map_err(|_| AppSendError)
+        self.sender.send(action).map_err(AppSendError::Rejected)?;
+        self.fetched.remove(feed.id);
+        Ok(())
+    }
+
+    fn trigger(&self, id: usize) -> Result<(), AppSendError> {
+        let action = SyncTask::trigger(id);
+        self.sender.send(action).map_err(AppSendError::Rejected)
+    }
+
+    fn history(&self, id: usize) -> Vec<Execution> {
+        self.handle.history(id)
+    }
+
+    fn latest(&self, id: usize) -> Option<Arc<Latest>> {
+        self.fetched.cache.get(id)
+    }
+
+    fn health(&self, id: usize) -> Option<Health> {
+        self.fetched.health.get(id)
+    }
+
+    fn metrics(&self) -> String {
+        self.metrics.render()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.handle.snapshot()
+    }
+
+    fn restored(&self) -> Vec<Feed> {
+        self.restored.clone()
     }
 }
 
@@ -107,17 +381,51 @@ where
     R: TaskSend<AsyncTask> + Send + 'static,
 {
     fn create(&self, feed: Feed) -> Result<(), AppSendError> {
-        let action = AsyncTask::new(feed.id, recurring_fetch(feed));
-        self.sender.send(action).map_err(|_| AppSendError)
+        let action = async_fetch_task(feed, self.fetched.clone())?;
+        self.sender.send(action).map_err(AppSendError::Rejected)
     }
 
     fn update(&self, feed: Feed) -> Result<(), AppSendError> {
-        let action = AsyncTask::update(feed.id, recurring_fetch(feed));
-        self.sender.send(action).map_err(|_| AppSendError)
+        let action = AsyncTask {
+            op: Operation::Update,
+            ..async_fetch_task(feed, self.fetched.clone())?
+        };
+        self.sender.send(action).map_err(AppSendError::Rejected)
     }
 
     fn delete(&self, feed: Feed) -> Result<(), AppSendError> {
         let action = AsyncTask::stop(feed.id);
-        self.sender.send(action).map_err(|_| AppSendError)
+        self.sender.send(action).map_err(AppSendError::Rejected)?;
+        self.fetched.remove(feed.id);
+        Ok(())
+    }
+
+    fn trigger(&self, id: usize) -> Result<(), AppSendError> {
+        let action = AsyncTask::trigger(id);
+        self.sender.send(action).map_err(AppSendError::Rejected)
+    }
+
+    fn history(&self, id: usize) -> Vec<Execution> {
+        self.handle.history(id)
+    }
+
+    fn latest(&self, id: usize) -> Option<Arc<Latest>> {
+        self.fetched.cache.get(id)
+    }
+
+    fn health(&self, id: usize) -> Option<Health> {
+        self.fetched.health.get(id)
+    }
+
+    fn metrics(&self) -> String {
+        self.metrics.render()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.handle.snapshot()
+    }
+
+    fn restored(&self) -> Vec<Feed> {
+        self.restored.clone()
     }
 }
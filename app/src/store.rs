@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use crate::{models::Feed, scheduler_interface::AppSendError};
+
+/// The feeds are kept in this file when the variable is set, and in memory otherwise.
+const FEED_STORE_VAR: &str = "TULSA_FEED_STORE";
+
+/// How long a retry with an idempotency key gets the feed the first request created.
+const KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Used to indicate a `FeedStore` could not read or write its feeds.
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    /// The stored feeds could not be parsed.
+    Corrupt(serde_json::Error),
+    /// A lock was poisoned by a thread which panicked while holding it.
+    Poisoned,
+    /// The scheduler rejected a new feed, so it was not stored.
+    Rejected(AppSendError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "feed store I/O failed: {}", e),
+            StoreError::Corrupt(e) => write!(f, "feed store is corrupt: {}", e),
+            StoreError::Poisoned => write!(f, "feed store lock is poisoned"),
+            StoreError::Rejected(e) => write!(f, "feed was rejected: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Corrupt(e)
+    }
+}
+
+/// Where the feeds of the API are kept, along with the id the next feed gets.
+pub trait FeedStore: Send + Sync {
+    fn get(&self, id: usize) -> Result<Option<Feed>, StoreError>;
+
+    /// Every feed, ordered by id.
+    fn list(&self) -> Result<Vec<Feed>, StoreError>;
+
+    /// Add a new feed under the next id, which `create` turns into the feed, e.g. by scheduling
+    /// it. Both happen under one lock, so two feeds never get the same id, and nothing is stored
+    /// if `create` fails. If the feed cannot be stored after `create` succeeded, undoing it is up
+    /// to the caller. A feed which was created with the same `idempotency_key` is returned
+    /// instead, without calling `create`.
+    fn create(
+        &self,
+        idempotency_key: Option<&str>,
+        create: &mut dyn FnMut(usize) -> Result<Feed, AppSendError>,
+    ) -> Result<Feed, StoreError>;
+
+    /// Add or replace a feed. The next id is moved past the id of the feed.
+    fn insert(&self, feed: Feed) -> Result<(), StoreError>;
+
+    fn remove(&self, id: usize) -> Result<Option<Feed>, StoreError>;
+}
+
+/// The contents of a store, which is also the format of a `FileStore`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Feeds {
+    next_id: usize,
+    feeds: BTreeMap<usize, Feed>,
+    /// The feed each idempotency key created, until the key expires.
+    #[serde(default)]
+    keys: BTreeMap<String, IdempotencyKey>,
+}
+
+/// A key which was sent with a `POST /feed`, which keeps the store from growing without bound by
+/// expiring after `KEY_TTL`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct IdempotencyKey {
+    feed_id: usize,
+    created_at: SystemTime,
+}
+
+impl Default for Feeds {
+    fn default() -> Self {
+        Self {
+            next_id: 1,
+            feeds: BTreeMap::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl Feeds {
+    fn create(
+        &mut self,
+        idempotency_key: Option<&str>,
+        create: &mut dyn FnMut(usize) -> Result<Feed, AppSendError>,
+    ) -> Result<Feed, StoreError> {
+        let now = SystemTime::now();
+        self.keys
+            .retain(|_, key| now.duration_since(key.created_at).unwrap_or_default() < KEY_TTL);
+        let existing = idempotency_key
+            .and_then(|key| self.keys.get(key))
+            .and_then(|key| self.feeds.get(&key.feed_id));
+        if let Some(feed) = existing {
+            return Ok(feed.clone());
+        }
+
+        let feed = create(self.next_id).map_err(StoreError::Rejected)?;
+        if let Some(key) = idempotency_key {
+            let saved = IdempotencyKey {
+                feed_id: feed.id,
+                created_at: now,
+            };
+            self.keys.insert(key.to_string(), saved);
+        }
+        self.insert(feed.clone());
+        Ok(feed)
+    }
+
+    fn insert(&mut self, feed: Feed) {
+        self.next_id = self.next_id.max(feed.id + 1);
+        self.feeds.insert(feed.id, feed);
+    }
+
+    fn remove(&mut self, id: usize) -> Option<Feed> {
+        self.keys.retain(|_, key| key.feed_id != id);
+        self.feeds.remove(&id)
+    }
+}
+
+/// A store which is lost when the process exits.
+#[derive(Default)]
+pub struct MemoryStore {
+    feeds: RwLock<Feeds>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl FeedStore for MemoryStore {
+    fn get(&self, id: usize) -> Result<Option<Feed>, StoreError> {
+        let feeds = self.feeds.read().map_err(|_| StoreError::Poisoned)?;
+        Ok(feeds.feeds.get(&id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<Feed>, StoreError> {
+        let feeds = self.feeds.read().map_err(|_| StoreError::Poisoned)?;
+        Ok(feeds.feeds.values().cloned().collect())
+    }
+
+    fn create(
+        &self,
+        idempotency_key: Option<&str>,
+        create: &mut dyn FnMut(usize) -> Result<Feed, AppSendError>,
+    ) -> Result<Feed, StoreError> {
+        self.feeds
+            .write()
+            .map_err(|_| StoreError::Poisoned)?
+            .create(idempotency_key, create)
+    }
+
+    fn insert(&self, feed: Feed) -> Result<(), StoreError> {
+        self.feeds
+            .write()
+            .map_err(|_| StoreError::Poisoned)?
+            .insert(feed);
+        Ok(())
+    }
+
+    fn remove(&self, id: usize) -> Result<Option<Feed>, StoreError> {
+        let mut feeds = self.feeds.write().map_err(|_| StoreError::Poisoned)?;
+        Ok(feeds.remove(id))
+    }
+}
+
+/// A store which keeps the feeds in a JSON file, so that they survive a restart. The file is
+/// rewritten on every change, which is fine for the few feeds an instance polls.
+pub struct FileStore {
+    path: PathBuf,
+    feeds: RwLock<Feeds>,
+}
+
+impl FileStore {
+    /// Open the store at `path`, which is created on the first change if it does not exist.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+        let path = path.into();
+        let feeds = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Feeds::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            feeds: RwLock::new(feeds),
+        })
+    }
+
+    /// Write the feeds to a temporary file and move it into place, so that a crash never leaves
+    /// a partly written store behind.
+    fn save(&self, feeds: &Feeds) -> Result<(), StoreError> {
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(feeds)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl FeedStore for FileStore {
+    fn get(&self, id: usize) -> Result<Option<Feed>, StoreError> {
+        let feeds = self.feeds.read().map_err(|_| StoreError::Poisoned)?;
+        Ok(feeds.feeds.get(&id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<Feed>, StoreError> {
+        let feeds = self.feeds.read().map_err(|_| StoreError::Poisoned)?;
+        Ok(feeds.feeds.values().cloned().collect())
+    }
+
+    fn create(
+        &self,
+        idempotency_key: Option<&str>,
+        create: &mut dyn FnMut(usize) -> Result<Feed, AppSendError>,
+    ) -> Result<Feed, StoreError> {
+        let mut feeds = self.feeds.write().map_err(|_| StoreError::Poisoned)?;
+        let mut changed = feeds.clone();
+        let feed = changed.create(idempotency_key, create)?;
+        self.save(&changed)?;
+        *feeds = changed;
+        Ok(feed)
+    }
+
+    fn insert(&self, feed: Feed) -> Result<(), StoreError> {
+        let mut feeds = self.feeds.write().map_err(|_| StoreError::Poisoned)?;
+        let mut changed = feeds.clone();
+        changed.insert(feed);
+        self.save(&changed)?;
+        *feeds = changed;
+        Ok(())
+    }
+
+    fn remove(&self, id: usize) -> Result<Option<Feed>, StoreError> {
+        let mut feeds = self.feeds.write().map_err(|_| StoreError::Poisoned)?;
+        let mut changed = feeds.clone();
+        let feed = changed.remove(id);
+        if feed.is_some() {
+            self.save(&changed)?;
+            *feeds = changed;
+        }
+        Ok(feed)
+    }
+}
+
+/// A `FileStore` at `TULSA_FEED_STORE` if it is set, and a `MemoryStore` otherwise.
+pub fn build() -> Result<Arc<dyn FeedStore>, StoreError> {
+    match env::var(FEED_STORE_VAR) {
+        Ok(path) => Ok(Arc::new(FileStore::open(path)?)),
+        Err(_) => Ok(Arc::new(MemoryStore::new())),
+    }
+}
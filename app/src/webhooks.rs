@@ -0,0 +1,302 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    health::{StaleReason, Transition},
+    models::Feed,
+};
+
+/// Staleness events are posted to each of these comma-separated URLs when the variable is set.
+const WEBHOOKS_VAR: &str = "TULSA_WEBHOOKS";
+
+/// How long a webhook may take to respond to a single attempt.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Stale,
+    Recovered,
+}
+
+/// The body posted to the webhooks when a feed becomes stale or recovers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub event: EventKind,
+    pub feed_id: usize,
+    pub feed_name: String,
+    /// The rules the feed broke, which are empty once it recovered.
+    pub reasons: Vec<StaleReason>,
+    /// Milliseconds since the Unix epoch.
+    pub at: u64,
+}
+
+impl Event {
+    pub fn new(feed: &Feed, transition: Transition) -> Self {
+        let (event, reasons) = match transition {
+            Transition::Stale(reasons) => (EventKind::Stale, reasons),
+            Transition::Recovered => (EventKind::Recovered, Vec::new()),
+        };
+        Self {
+            event,
+            feed_id: feed.id,
+            feed_name: feed.name.clone(),
+            reasons,
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+}
+
+/// How often a delivery is attempted, and the delay before the first retry, which doubles after
+/// each one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Posts events to HTTP webhooks. Each webhook gets the events in order on a thread of its own,
+/// so that a slow webhook never holds up a fetch or the other webhooks.
+#[derive(Debug, Default)]
+pub struct Webhooks {
+    /// One for each webhook.
+    senders: Vec<Sender<Event>>,
+}
+
+impl Webhooks {
+    /// Start delivering to `urls`, retrying each failed delivery as `retry` says.
+    pub fn start(urls: Vec<String>, retry: Retry) -> Self {
+        let senders = urls
+            .into_iter()
+            .map(|url| {
+                let (sender, receiver) = channel::<Event>();
+                thread::spawn(move || {
+                    let agent = ureq::AgentBuilder::new().timeout(DELIVERY_TIMEOUT).build();
+                    for event in receiver {
+                        deliver(&agent, &url, &event, retry);
+                    }
+                });
+                sender
+            })
+            .collect();
+        Self { senders }
+    }
+
+    pub fn send(&self, event: Event) {
+        for sender in self.senders.iter() {
+            // A thread only stops when its sender is dropped.
+            let _ = sender.send(event.clone());
+        }
+    }
+}
+
+fn deliver(agent: &ureq::Agent, url: &str, event: &Event, retry: Retry) {
+    // An event always serializes, since it only holds strings and numbers.
+    let body = serde_json::to_string(event).unwrap();
+    let mut backoff = retry.backoff;
+    for attempt in 1..=retry.attempts {
+        let response = agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+        match response {
+            Ok(_) => {
+                info!(
+                    "Sent {:?} event of feed {} to {}",
+                    event.event, event.feed_id, url
+                );
+                return;
+            }
+            Err(e) if attempt < retry.attempts => {
+                warn!(
+                    "Attempt {} to send an event to {} failed: {}",
+                    attempt, url, e
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(e) => error!(
+                "Giving up on sending an event to {} after {} attempts: {}",
+                url, attempt, e
+            ),
+        }
+    }
+}
+
+/// `Webhooks` for the URLs in `TULSA_WEBHOOKS`, which are none if it is not set.
+pub fn build() -> Webhooks {
+    let urls = env::var(WEBHOOKS_VAR)
+        .map(|urls| {
+            urls.split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Webhooks::start(urls, Retry::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// The events a stub webhook received, and how many requests it answered.
+    #[derive(Default)]
+    struct Received {
+        events: Vec<Event>,
+        requests: usize,
+    }
+
+    /// Start a webhook which fails its first `failures` requests, and returns its URL.
+    async fn stub(failures: usize, received: Arc<Mutex<Received>>) -> String {
+        async fn receive(
+            State((failures, received)): State<(usize, Arc<Mutex<Received>>)>,
+            Json(event): Json<Event>,
+        ) -> StatusCode {
+            let mut received = received.lock().unwrap();
+            received.requests += 1;
+            if received.requests <= failures {
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            received.events.push(event);
+            StatusCode::NO_CONTENT
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = Router::new()
+            .route("/hook", post(receive))
+            .with_state((failures, received));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}/hook", address)
+    }
+
+    async fn wait_for(received: &Mutex<Received>, events: usize) {
+        for _ in 0..200 {
+            if received.lock().unwrap().events.len() >= events {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("the webhook did not receive {} events", events);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delivery() {
+        let flaky = Arc::new(Mutex::new(Received::default()));
+        let steady = Arc::new(Mutex::new(Received::default()));
+        let urls = vec![stub(2, flaky.clone()).await, stub(0, steady.clone()).await];
+        let webhooks = Webhooks::start(
+            urls,
+            Retry {
+                attempts: 3,
+                backoff: Duration::from_millis(10),
+            },
+        );
+
+        let feed = Feed {
+            id: 7,
+            name: "MTA".to_string(),
+            ..Feed::test("http")
+        };
+        webhooks.send(Event::new(
+            &feed,
+            Transition::Stale(vec![StaleReason::HeaderAge]),
+        ));
+        webhooks.send(Event::new(&feed, Transition::Recovered));
+
+        wait_for(&flaky, 2).await;
+        wait_for(&steady, 2).await;
+
+        // The flaky webhook got the first event on the third attempt, and both in order
+        let flaky = flaky.lock().unwrap();
+        assert_eq!(flaky.requests, 4);
+        assert_eq!(flaky.events[0].event, EventKind::Stale);
+        assert_eq!(flaky.events[0].feed_id, 7);
+        assert_eq!(flaky.events[0].feed_name, "MTA");
+        assert_eq!(flaky.events[0].reasons, [StaleReason::HeaderAge]);
+        assert_eq!(flaky.events[1].event, EventKind::Recovered);
+        assert!(flaky.events[1].reasons.is_empty());
+        assert_eq!(steady.lock().unwrap().events, flaky.events);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn give_up() {
+        let down = Arc::new(Mutex::new(Received::default()));
+        let up = Arc::new(Mutex::new(Received::default()));
+        let urls = vec![
+            stub(usize::MAX, down.clone()).await,
+            stub(0, up.clone()).await,
+        ];
+        let webhooks = Webhooks::start(
+            urls,
+            Retry {
+                attempts: 2,
+                backoff: Duration::from_millis(10),
+            },
+        );
+
+        let feed = Feed {
+            name: "MTA".to_string(),
+            ..Feed::test("http")
+        };
+        webhooks.send(Event::new(&feed, Transition::Recovered));
+
+        wait_for(&up, 1).await;
+        for _ in 0..200 {
+            if down.lock().unwrap().requests >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(down.lock().unwrap().requests, 2);
+        assert!(down.lock().unwrap().events.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn slow_webhook() {
+        let down = Arc::new(Mutex::new(Received::default()));
+        let up = Arc::new(Mutex::new(Received::default()));
+        let urls = vec![
+            stub(usize::MAX, down.clone()).await,
+            stub(0, up.clone()).await,
+        ];
+        let webhooks = Webhooks::start(
+            urls,
+            Retry {
+                attempts: 2,
+                backoff: Duration::from_secs(60),
+            },
+        );
+
+        // The other webhook gets the events while the one which is down waits to retry
+        webhooks.send(Event::new(&Feed::test("http"), Transition::Recovered));
+        webhooks.send(Event::new(&Feed::test("http"), Transition::Recovered));
+        wait_for(&up, 2).await;
+        assert_eq!(down.lock().unwrap().requests, 1);
+    }
+}
@@ -1,26 +1,37 @@
-use std::sync::mpsc;
 use std::time::Duration;
 use std::thread;
 use tokio::time::interval;
-use tulsa::{AsyncTask, Scheduler, SyncTask};
+use tulsa::{channel, AsyncTask, Limits, Scheduler, SyncTask};
+
+/// The tasks run every 10ms, so only the limit on registered tasks stops them.
+fn limits() -> Limits {
+    Limits {
+        min_frequency: Duration::from_millis(10),
+        queue_depth: 10000,
+        ..Limits::default()
+    }
+}
+
+/// Never returns, like the feeds which run until they are stopped.
+async fn tick() {
+    let duration = Duration::from_millis(10);
+    let mut interval = interval(duration);
+
+    loop {
+        interval.tick().await;
+    }
+}
 
 fn run_async() {
-    let (sender, receiver) = mpsc::channel();
+    let (sender, receiver) = channel(limits());
     Scheduler::<AsyncTask>::new(receiver).run();
 
     for i in 1..10000 {
-        let task = AsyncTask::new(i, async {
-            let duration = Duration::from_millis(10);
-            let mut interval = interval(duration);
-
-            loop {
-                interval.tick().await;
-            }
-        });
+        let task = AsyncTask::new(i, tick());
 
         match sender.send(task) {
             Ok(_) => {},
-            Err(e) => panic!("{}", e),
+            Err(e) => eprintln!("{}", e),
         };
     }
 
@@ -28,7 +39,7 @@ fn run_async() {
 }
 
 fn run_sync() {
-    let (sender, receiver) = mpsc::channel();
+    let (sender, receiver) = channel(limits());
     Scheduler::<SyncTask>::new(receiver).run();
 
     for i in 1..10000 {
@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    metrics::{Metrics, NoopMetrics},
+    model::{Operation, Task},
+};
+
+/// Bounds on the resources a scheduler can be asked to use.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// The maximum number of tasks which can be registered at once.
+    pub max_tasks: usize,
+    /// The shortest time allowed between two executions of a recurring task.
+    pub min_frequency: Duration,
+    /// The maximum number of commands waiting to be handled by the scheduler, or by each of its
+    /// shards.
+    pub queue_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_tasks: 1000,
+            min_frequency: Duration::from_secs(1),
+            queue_depth: 100,
+        }
+    }
+}
+
+/// Used to indicate a command was not sent to the scheduler.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdmissionError {
+    TooManyTasks(usize),
+    FrequencyTooLow(Duration),
+    QueueFull(usize),
+    Disconnected,
+}
+
+impl fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdmissionError::TooManyTasks(max) => {
+                write!(f, "the limit of {} registered tasks has been reached", max)
+            }
+            AdmissionError::FrequencyTooLow(min) => {
+                write!(f, "the frequency must be at least {:?}", min)
+            }
+            AdmissionError::QueueFull(depth) => {
+                write!(f, "the queue of {} pending commands is full", depth)
+            }
+            AdmissionError::Disconnected => write!(f, "the scheduler is no longer running"),
+        }
+    }
+}
+
+impl std::error::Error for AdmissionError {}
+
+/// Held by an admitted one-shot task until it has run, so that the task stops counting against
+/// `max_tasks` once it is dropped.
+#[derive(Clone, Debug, Default)]
+pub struct Ticket(Arc<()>);
+
+impl Ticket {
+    fn is_held(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+}
+
+/// The tasks which were admitted so far, which are checked against the `Limits` of a new one.
+pub(crate) struct Admission {
+    limits: Limits,
+    registered: HashSet<usize>,
+    /// The one-shot tasks, which are only registered until they have run.
+    once: HashMap<usize, Ticket>,
+}
+
+impl Admission {
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            registered: HashSet::new(),
+            once: HashMap::new(),
+        }
+    }
+
+    /// Whether `task` can be admitted without going over the limits.
+    pub(crate) fn check<T: Task>(&mut self, task: &T) -> Result<(), AdmissionError> {
+        if matches!(task.op(), Operation::Create | Operation::Update) {
+            self.once.retain(|_, ticket| ticket.is_held());
+            let id = task.id();
+            let known = self.registered.contains(&id) || self.once.contains_key(&id);
+            if !known && self.registered.len() + self.once.len() >= self.limits.max_tasks {
+                return Err(AdmissionError::TooManyTasks(self.limits.max_tasks));
+            }
+
+            // An adaptive task can run as often as its lower bound allows.
+            let bounds = task.bounds().map(|bounds| bounds.min);
+            if let Some(frequency) = task.frequency().into_iter().chain(bounds).min() {
+                if frequency < self.limits.min_frequency {
+                    return Err(AdmissionError::FrequencyTooLow(self.limits.min_frequency));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Give a one-shot task which is about to be sent the ticket it holds until it has run.
+    pub(crate) fn ticket<T: Task>(task: &mut T) -> Option<Ticket> {
+        let registers = matches!(task.op(), Operation::Create | Operation::Update);
+        if !registers || task.frequency().is_some() {
+            return None;
+        }
+        let ticket = Ticket::default();
+        task.hold(ticket.clone());
+        Some(ticket)
+    }
+
+    /// Count a task which was admitted, along with its ticket if it is a one-shot task.
+    pub(crate) fn admit(&mut self, id: usize, op: Operation, ticket: Option<Ticket>) {
+        match op {
+            Operation::Create | Operation::Update => match ticket {
+                Some(ticket) => {
+                    self.registered.remove(&id);
+                    self.once.insert(id, ticket);
+                }
+                None => {
+                    self.once.remove(&id);
+                    self.registered.insert(id);
+                }
+            },
+            Operation::Delete => {
+                self.registered.remove(&id);
+                self.once.remove(&id);
+            }
+            Operation::Trigger => {}
+        }
+    }
+}
+
+/// The sending half of a channel created by [`channel`], which rejects commands over its `Limits`
+/// instead of queueing them.
+pub struct TaskSender<T> {
+    sender: SyncSender<T>,
+    queue_depth: usize,
+    admission: Arc<Mutex<Admission>>,
+    metrics: Arc<dyn Metrics>,
+}
+
+impl<T> Clone for TaskSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            queue_depth: self.queue_depth,
+            admission: self.admission.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<T> TaskSender<T> {
+    /// Count every queued command in `metrics`, which measures the depth of the command queue
+    /// when the scheduler is given the same metrics.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+}
+
+impl<T> TaskSender<T>
+where
+    T: Task,
+{
+    pub fn send(&self, mut task: T) -> Result<(), AdmissionError> {
+        // Hold the lock until the task is queued so that concurrent senders are counted correctly.
+        let mut admission = self.admission.lock().unwrap();
+        let (id, op) = (task.id(), task.op());
+        admission.check(&task)?;
+        let ticket = Admission::ticket(&mut task);
+
+        self.sender.try_send(task).map_err(|e| match e {
+            TrySendError::Full(_) => AdmissionError::QueueFull(self.queue_depth),
+            TrySendError::Disconnected(_) => AdmissionError::Disconnected,
+        })?;
+        self.metrics.command_queued();
+        admission.admit(id, op, ticket);
+        Ok(())
+    }
+}
+
+/// Create a bounded channel for a `Scheduler` which enforces `limits` when a command is sent.
+/// Give the scheduler the same `limits` to also enforce them on commands from other senders.
+pub fn channel<T>(limits: Limits) -> (TaskSender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::sync_channel(limits.queue_depth);
+    let sender = TaskSender {
+        sender,
+        queue_depth: limits.queue_depth,
+        admission: Arc::new(Mutex::new(Admission::new(limits))),
+        metrics: Arc::new(NoopMetrics),
+    };
+    (sender, receiver)
+}
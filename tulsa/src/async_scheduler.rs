@@ -1,26 +1,47 @@
+use chrono_tz::Tz;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    future::Future,
     sync::{mpsc::Receiver, Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::{runtime::Builder as TokioBuilder, task::JoinHandle as TaskJoinHandle};
+use tokio::{runtime::Builder as TokioBuilder, task::JoinSet, time::Instant as TokioInstant};
+use tracing::{info, info_span, warn, Instrument, Span};
 
-use crate::model::{AsyncTask, Operation};
+use crate::{
+    context::TaskContext,
+    graph::AsyncGraph,
+    handle::{SchedulerHandle, TaskControl, Wake},
+    history::{panic_message, Execution, Outcome},
+    model::{AsyncBody, AsyncFunc, AsyncTask, Bounds, Operation, Report, Task},
+    overlap::{Admit, Run, RunKind},
+    process::ProcessTask,
+    snapshot::{to_instant, to_system_time},
+    window::ServiceWindow,
+};
 
 pub(crate) struct AsyncScheduler {
-    tasks: HashMap<usize, TaskJoinHandle<()>>,
+    tasks: HashMap<usize, Arc<TaskControl>>,
     num_runtime_threads: usize,
+    handle: SchedulerHandle,
+    /// The zone of a service window which does not have one of its own.
+    zone: Tz,
+    grace_period: Duration,
 }
 
 impl AsyncScheduler {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(handle: SchedulerHandle, zone: Tz, grace_period: Duration) -> Self {
         AsyncScheduler {
             tasks: HashMap::new(),
             num_runtime_threads: 1,
+            handle,
+            zone,
+            grace_period,
         }
     }
 
     pub(crate) fn listen(&mut self, receiver: Arc<Mutex<Receiver<AsyncTask>>>) {
-        println!("AsyncScheduler initialized.");
+        info!("AsyncScheduler initialized.");
 
         let runtime = TokioBuilder::new_multi_thread()
             .enable_all()
@@ -34,33 +55,324 @@ impl AsyncScheduler {
         runtime.block_on(async {
             loop {
                 match r.lock().unwrap().recv() {
-                    Ok(async_task) => self.handle(async_task),
-                    Err(e) => eprintln!("{}", e),
+                    Ok(async_task) => {
+                        self.handle.metrics.command_received();
+                        self.handle(async_task);
+                    }
+                    Err(e) => {
+                        info!("Stopped listening: {}", e);
+                        break;
+                    }
                 }
             }
+
+            // Every sender is gone, but the tasks keep running on this runtime.
+            std::future::pending::<()>().await;
         });
     }
 
     fn start(&mut self, task: AsyncTask) {
-        let future = tokio::spawn(task.func);
-        self.tasks.insert(task.id, future);
+        let id = task.id;
+        let frequency = task.frequency;
+        let scheduler = self.handle.clone();
+        let span = task.span();
+        let control = Arc::new(TaskControl::new(
+            task.frequency(),
+            task.bounds,
+            task.overlap,
+            self.grace_period,
+            task.spec.clone(),
+        ));
+
+        // A one-shot task keeps its ticket until it has run, so that it counts against the limits.
+        let ticket = task.ticket;
+        let body = match task.func {
+            // A one-shot task waits for the lease, like the once-graph of a thread scheduler.
+            AsyncFunc::Once(func) => {
+                let future = async move {
+                    let _ticket = ticket;
+                    scheduler.leading().await;
+                    let (execution, _) = execute(func).await;
+                    scheduler.record(id, execution);
+                };
+                // The future cannot see a context, so there is no point in waiting for it.
+                return self.spawn(id, control, Duration::ZERO, future.instrument(span));
+            }
+            AsyncFunc::Graph { graph, once: true } => {
+                scheduler
+                    .history
+                    .lock()
+                    .unwrap()
+                    .add_nodes(id, graph.node_ids());
+                let task_control = control.clone();
+                let future = async move {
+                    let _ticket = ticket;
+                    scheduler.leading().await;
+                    let run = task_control.next_run();
+                    let context = TaskContext::new(id, run, SystemTime::now(), task_control);
+                    execute_graph(&graph, &scheduler, &context).await
+                };
+                return self.spawn(id, control, self.grace_period, future.instrument(span));
+            }
+            AsyncFunc::Graph { graph, .. } => {
+                scheduler
+                    .history
+                    .lock()
+                    .unwrap()
+                    .add_nodes(id, graph.node_ids());
+                Body::Graph(graph)
+            }
+            AsyncFunc::Recurring(factory) => Body::Recurring(factory),
+            AsyncFunc::Process(process) => Body::Process(process),
+        };
+
+        let work = Arc::new(Work {
+            id,
+            body,
+            bounds: task.bounds,
+            window: task.window.map(|window| window.or_zone(self.zone)),
+            control: control.clone(),
+            scheduler,
+            span: span.clone(),
+        });
+        let future = recur(frequency, work).instrument(span);
+        self.spawn(id, control, self.grace_period, future);
+    }
+
+    fn spawn<F>(&mut self, id: usize, control: Arc<TaskControl>, grace_period: Duration, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // Registered first, so that a restored task has its schedule before it starts.
+        self.handle.register(id, control.clone());
+        tokio::spawn(until_stopped(control.clone(), grace_period, future));
+        self.tasks.insert(id, control);
     }
 
     fn stop(&mut self, task_id: usize) {
-        let task = &self.tasks[&task_id];
-        task.abort_handle().abort();
-        self.tasks.remove(&task_id);
-        println!("Stopped {}", task_id);
+        self.handle.unregister(task_id);
+        if let Some(control) = self.tasks.remove(&task_id) {
+            control.stop();
+            info!(id = task_id, "Stopping task");
+        }
     }
 
     fn handle(&mut self, task: AsyncTask) {
+        if self.handle.is_shut_down() && task.op != Operation::Delete {
+            warn!(id = task.id, "Ignoring task after shutdown");
+            return;
+        }
+        // A recurring task without a frequency would run without a pause, so it is left out and
+        // the task it updates keeps running.
+        let registers = matches!(task.op, Operation::Create | Operation::Update);
+        if registers && task.frequency() == Some(Duration::ZERO) {
+            warn!(id = task.id, "Ignoring recurring task without a frequency");
+            return;
+        }
+
         match task.op {
             Operation::Create => self.start(task),
-            Operation::Delete => self.stop(task.id),
+            Operation::Delete => {
+                self.stop(task.id);
+                self.handle.history.lock().unwrap().remove(task.id);
+            }
             Operation::Update => {
                 self.stop(task.id);
                 self.start(task);
             }
+            Operation::Trigger => {
+                if !self.handle.trigger(task.id) {
+                    warn!(id = task.id, "Cannot trigger unknown task");
+                }
+            }
+        }
+    }
+}
+
+/// The part of an `AsyncFunc` which is run on every tick of the task frequency.
+enum Body {
+    Recurring(AsyncBody),
+    Graph(AsyncGraph),
+    Process(ProcessTask),
+}
+
+impl Body {
+    /// Run the body once, returning the delay it suggested for the next execution.
+    async fn run(&self, context: TaskContext, scheduler: &SchedulerHandle) -> Option<Duration> {
+        let id = context.id();
+        let (execution, next_delay) = match self {
+            Body::Recurring(factory) => execute(factory(context)).await,
+            Body::Graph(graph) => {
+                execute_graph(graph, scheduler, &context).await;
+                return None;
+            }
+            // A process cannot see the context, so it is killed as soon as the task is stopped.
+            Body::Process(process) => tokio::select! {
+                execution = process.run_async() => (execution, None),
+                _ = context.cancelled() => return None,
+            },
+        };
+        scheduler.record(id, execution);
+        next_delay
+    }
+}
+
+/// What the executions of a recurring task share.
+struct Work {
+    id: usize,
+    body: Body,
+    bounds: Option<Bounds>,
+    window: Option<ServiceWindow>,
+    control: Arc<TaskControl>,
+    scheduler: SchedulerHandle,
+    span: Span,
+}
+
+impl Work {
+    /// Run the task, then any run which was queued behind it.
+    async fn run(self: Arc<Self>, mut run: Run) {
+        loop {
+            let number = self.control.next_run();
+            let context = TaskContext::new(self.id, number, run.scheduled_at, self.control.clone());
+            let lag = SystemTime::now().duration_since(run.scheduled_at);
+            self.scheduler
+                .metrics
+                .execution_started(self.id, lag.unwrap_or_default());
+            let span = info_span!(parent: &self.span, "execution", run = number, kind = ?run.kind);
+            let next_delay = self
+                .body
+                .run(context, &self.scheduler)
+                .instrument(span)
+                .await;
+
+            // A triggered execution does not change the schedule.
+            if let (RunKind::Tick, Some(bounds), Some(delay)) = (run.kind, self.bounds, next_delay)
+            {
+                self.control.reschedule(bounds.clamp(delay));
+            }
+
+            match self.control.finish() {
+                Some(queued) if !self.control.is_stopping() => run = queued,
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Run the task on every tick of `frequency`, and whenever it is triggered. Each execution is
+/// spawned so that the overlap policy of the task can be applied to ticks which arrive during a
+/// run. Once the task is stopped, this waits for the executions in progress, and dropping this
+/// future aborts them along with it.
+async fn recur(frequency: Duration, work: Arc<Work>) {
+    let control = work.control.clone();
+    // A task restored from a snapshot keeps the tick it was waiting for, and the frequency it
+    // adapted to.
+    let frequency = control.frequency().unwrap_or(frequency);
+    let mut next_tick = control
+        .next_tick()
+        .map_or_else(TokioInstant::now, |at| to_instant(at).into());
+    let mut interval = tokio::time::interval_at(next_tick, frequency);
+    let mut runs = JoinSet::new();
+
+    loop {
+        control.set_next_tick(to_system_time(next_tick.into_std()));
+        let run = tokio::select! {
+            tick = interval.tick() => {
+                next_tick = tick + interval.period();
+                let closed = work.window.is_some_and(|w| !w.is_open(SystemTime::now()));
+                if control.is_paused() || closed || !work.scheduler.is_leader() {
+                    continue;
+                }
+                // The tick was due at `tick`, which may have been a moment ago.
+                Run::new(RunKind::Tick, SystemTime::now() - tick.elapsed())
+            }
+            _ = control.stopped() => break,
+            wake = control.woken() => match wake {
+                Wake::Reschedule => {
+                    let frequency = control.frequency().unwrap_or(frequency);
+                    next_tick = TokioInstant::now() + frequency;
+                    interval = tokio::time::interval_at(next_tick, frequency);
+                    continue;
+                }
+                _ => Run::new(RunKind::Trigger, SystemTime::now()),
+            },
+            Some(_) = runs.join_next() => continue,
+        };
+
+        match control.admit(run) {
+            Admit::Start => {
+                runs.spawn(work.clone().run(run));
+            }
+            Admit::Queue => {}
+            Admit::Skip => work.scheduler.metrics.tick_skipped(work.id),
+            Admit::Merge => {
+                info!(
+                    id = work.id,
+                    "Merged a trigger with the run which is waiting"
+                );
+                work.scheduler.metrics.trigger_merged(work.id);
+            }
         }
     }
+
+    while runs.join_next().await.is_some() {}
+}
+
+/// Run `future` until its task is stopped, then give it `grace_period` to finish before it is
+/// dropped, which aborts it.
+async fn until_stopped<F>(control: Arc<TaskControl>, grace_period: Duration, future: F)
+where
+    F: Future<Output = ()>,
+{
+    tokio::pin!(future);
+    tokio::select! {
+        _ = &mut future => return,
+        _ = control.stopped() => {}
+    }
+    let _ = tokio::time::timeout(grace_period, future).await;
+}
+
+/// Run each node of a graph in topological order, skipping the nodes with a dependency which did
+/// not succeed during this run.
+async fn execute_graph(graph: &AsyncGraph, scheduler: &SchedulerHandle, context: &TaskContext) {
+    let mut failed = HashSet::new();
+
+    for idx in graph.order.iter() {
+        let node = &graph.nodes[*idx];
+        let execution = match graph.failed_dependency(node.id, &failed) {
+            Some(dependency) => Execution::skipped(dependency),
+            None => execute((node.body)(context.clone())).await.0,
+        };
+
+        if execution.outcome != Outcome::Success {
+            failed.insert(node.id);
+        }
+        scheduler.record(node.id, execution);
+    }
+}
+
+/// Run a single execution of a task body. The body is spawned onto a `JoinSet` so that a panic is
+/// caught, and so that aborting the caller also aborts the body.
+async fn execute<F>(future: F) -> (Execution, Option<Duration>)
+where
+    F: Future<Output = Report> + Send + 'static,
+{
+    let started_at = SystemTime::now();
+    let start = Instant::now();
+
+    let mut set = JoinSet::new();
+    // The body runs on its own tokio task, so it is given the span of the execution.
+    set.spawn(future.in_current_span());
+    let execution = match set.join_next().await {
+        Some(Ok(report)) => {
+            let execution = Execution::new(started_at, start.elapsed(), report.result);
+            return (execution, report.next_delay);
+        }
+        Some(Err(e)) if e.is_panic() => {
+            Execution::panicked(started_at, start.elapsed(), panic_message(e.into_panic()))
+        }
+        // The body can only be cancelled by aborting this future, so this is never reached.
+        _ => Execution::new(started_at, start.elapsed(), Err("cancelled".to_string())),
+    };
+    (execution, None)
 }
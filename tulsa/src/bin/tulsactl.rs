@@ -0,0 +1,69 @@
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    process,
+};
+
+use tulsa::control::{Request, Response, DEFAULT_SOCKET_PATH};
+
+const USAGE: &str = "usage: tulsactl [--socket PATH] list|history <id>|pause <id>|resume <id>|trigger <id>|snapshot|shutdown";
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let mut socket = DEFAULT_SOCKET_PATH.to_string();
+    if args.first().map(String::as_str) == Some("--socket") {
+        if args.len() < 2 {
+            exit(USAGE);
+        }
+        socket = args.remove(1);
+        args.remove(0);
+    }
+
+    let request = match parse(&args) {
+        Some(request) => request,
+        None => exit(USAGE),
+    };
+
+    match send(&socket, &request) {
+        Ok(response) => {
+            println!("{}", serde_json::to_string_pretty(&response).unwrap());
+            if let Response::Error { .. } = response {
+                process::exit(1);
+            }
+        }
+        Err(e) => exit(&format!("failed to reach {}: {}", socket, e)),
+    }
+}
+
+fn parse(args: &[String]) -> Option<Request> {
+    let id = || args.get(1)?.parse().ok();
+
+    let request = match args.first()?.as_str() {
+        "list" => Request::List,
+        "history" => Request::History { id: id()? },
+        "pause" => Request::Pause { id: id()? },
+        "resume" => Request::Resume { id: id()? },
+        "trigger" => Request::Trigger { id: id()? },
+        "snapshot" => Request::Snapshot,
+        "shutdown" => Request::Shutdown,
+        _ => return None,
+    };
+    Some(request)
+}
+
+fn send(socket: &str, request: &Request) -> Result<Response, Box<dyn std::error::Error>> {
+    let mut stream = UnixStream::connect(socket)?;
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
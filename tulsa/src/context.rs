@@ -0,0 +1,64 @@
+use std::{fmt, sync::Arc, time::SystemTime};
+
+use crate::handle::TaskControl;
+
+/// What the body of a task is told about the execution it is running.
+#[derive(Clone)]
+pub struct TaskContext {
+    id: usize,
+    run: u64,
+    scheduled_at: SystemTime,
+    control: Arc<TaskControl>,
+}
+
+impl TaskContext {
+    pub(crate) fn new(
+        id: usize,
+        run: u64,
+        scheduled_at: SystemTime,
+        control: Arc<TaskControl>,
+    ) -> Self {
+        Self {
+            id,
+            run,
+            scheduled_at,
+            control,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The number of this execution, counting from 1 since the task was created or updated.
+    pub fn run(&self) -> u64 {
+        self.run
+    }
+
+    /// When the execution was due. For a triggered execution, this is when the trigger arrived.
+    pub fn scheduled_at(&self) -> SystemTime {
+        self.scheduled_at
+    }
+
+    /// Returns `true` once the task has been asked to stop. The body should clean up and return
+    /// before the grace period of its scheduler is over, after which it is aborted.
+    pub fn is_cancelled(&self) -> bool {
+        self.control.is_stopping()
+    }
+
+    /// Wait until the task is asked to stop, e.g. in a `select!` next to the work of an async body.
+    pub async fn cancelled(&self) {
+        self.control.stopped().await
+    }
+}
+
+impl fmt::Debug for TaskContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskContext")
+            .field("id", &self.id)
+            .field("run", &self.run)
+            .field("scheduled_at", &self.scheduled_at)
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    thread::{sleep, Builder as ThreadBuilder},
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::{
+    handle::{SchedulerHandle, TaskInfo},
+    history::Execution,
+    snapshot::Snapshot,
+};
+
+/// Where `tulsactl` looks for the control socket when no path is given.
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/tulsa.sock";
+
+/// How often the control thread checks whether the scheduler was shut down while no client
+/// connects.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A command sent to the control socket, as a single line of JSON.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    List,
+    History { id: usize },
+    Pause { id: usize },
+    Resume { id: usize },
+    Trigger { id: usize },
+    Snapshot,
+    Shutdown,
+}
+
+/// The reply to a `Request`, as a single line of JSON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Tasks { tasks: Vec<TaskInfo> },
+    History { executions: Vec<Execution> },
+    Snapshot { snapshot: Snapshot },
+    Error { message: String },
+}
+
+/// Bind a Unix socket at `path` and answer requests against `handle` on a background thread,
+/// until the scheduler is shut down. The socket is removed then.
+pub(crate) fn serve(path: &Path, handle: SchedulerHandle) -> io::Result<()> {
+    // A socket left behind by a previous run would make the bind fail, but any other file at
+    // `path` is kept.
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            let message = format!("{} exists and is not a socket", path.display());
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, message));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    // Only the owner of the scheduler can steer it.
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    // Accepting without blocking lets the thread notice a shutdown while no client connects.
+    listener.set_nonblocking(true)?;
+    let path = path.to_path_buf();
+
+    ThreadBuilder::new()
+        .name("control".to_string())
+        .spawn(move || {
+            while !handle.is_shut_down() {
+                // A connection is answered with blocking reads, unlike the listener.
+                let accepted = listener.accept().and_then(|(stream, _)| {
+                    stream.set_nonblocking(false)?;
+                    Ok(stream)
+                });
+                match accepted {
+                    Ok(stream) => {
+                        let handle = handle.clone();
+                        let path = path.clone();
+                        let _ = ThreadBuilder::new()
+                            .name("control-connection".to_string())
+                            .spawn(move || {
+                                if let Err(e) = answer(stream, &handle, &path) {
+                                    warn!("Control connection failed: {}", e);
+                                }
+                            });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => sleep(ACCEPT_INTERVAL),
+                    Err(e) => warn!("Control connection failed: {}", e),
+                }
+            }
+
+            drop(listener);
+            let _ = fs::remove_file(&path);
+        })?;
+
+    Ok(())
+}
+
+fn answer(stream: UnixStream, handle: &SchedulerHandle, path: &Path) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let response = match serde_json::from_str::<Request>(&line?) {
+            Ok(request) => {
+                let shutdown = request == Request::Shutdown;
+                let response = respond(request, handle);
+                if shutdown {
+                    // Stop new connections, which are refused once the socket is gone.
+                    let _ = fs::remove_file(path);
+                }
+                response
+            }
+            Err(e) => Response::Error {
+                message: format!("invalid request: {}", e),
+            },
+        };
+
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+fn respond(request: Request, handle: &SchedulerHandle) -> Response {
+    let (id, found) = match request {
+        Request::List => {
+            let tasks = handle.tasks();
+            return Response::Tasks { tasks };
+        }
+        Request::History { id } => {
+            let executions = handle.history(id);
+            return Response::History { executions };
+        }
+        Request::Pause { id } => (id, handle.pause(id)),
+        Request::Resume { id } => (id, handle.resume(id)),
+        Request::Trigger { id } => (id, handle.trigger(id)),
+        Request::Snapshot => {
+            let snapshot = handle.snapshot();
+            return Response::Snapshot { snapshot };
+        }
+        Request::Shutdown => {
+            handle.shutdown();
+            return Response::Ok;
+        }
+    };
+
+    if found {
+        Response::Ok
+    } else {
+        Response::Error {
+            message: format!("task {} is not registered", id),
+        }
+    }
+}
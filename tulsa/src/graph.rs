@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    future::Future,
+};
+
+use crate::model::{async_body, sync_body, AsyncBody, SyncBody, TaskResult};
+
+/// Used to indicate a `Graph` cannot be scheduled.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GraphError {
+    /// Two nodes were given the same id.
+    DuplicateNode(usize),
+    /// An edge refers to a node which was never added.
+    UnknownNode(usize),
+    /// The edges form a cycle, so these nodes could never run.
+    Cycle(Vec<usize>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::DuplicateNode(id) => write!(f, "node {} was added more than once", id),
+            GraphError::UnknownNode(id) => write!(f, "edge refers to unknown node {}", id),
+            GraphError::Cycle(ids) => write!(f, "nodes {:?} are part of a cycle", ids),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+pub(crate) struct Node<B> {
+    pub(crate) id: usize,
+    pub(crate) body: B,
+}
+
+/// A set of task bodies where an edge from `a` to `b` means `b` only runs after a successful run
+/// of `a`. When a node fails, every node which depends on it is skipped for that run.
+///
+/// Node ids share a namespace with task ids, as each node keeps its own execution history.
+pub struct Graph<B> {
+    pub(crate) nodes: Vec<Node<B>>,
+    edges: Vec<(usize, usize)>,
+    /// Indices into `nodes` in topological order, which is filled in by `validate`.
+    pub(crate) order: Vec<usize>,
+}
+
+pub type SyncGraph = Graph<SyncBody>;
+pub type AsyncGraph = Graph<AsyncBody>;
+
+impl<B> Graph<B> {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Run `to` after every successful run of `from`.
+    pub fn edge(mut self, from: usize, to: usize) -> Self {
+        self.edges.push((from, to));
+        self
+    }
+
+    pub(crate) fn node_ids(&self) -> Vec<usize> {
+        self.nodes.iter().map(|node| node.id).collect()
+    }
+
+    /// Returns the first dependency of `id` which is in `failed`, if any.
+    pub(crate) fn failed_dependency(&self, id: usize, failed: &HashSet<usize>) -> Option<usize> {
+        self.edges
+            .iter()
+            .find(|(from, to)| *to == id && failed.contains(from))
+            .map(|(from, _)| *from)
+    }
+
+    /// Check the graph for duplicate nodes, unknown nodes and cycles, then store a topological
+    /// order of the nodes using Kahn's algorithm.
+    pub(crate) fn validate(mut self) -> Result<Self, GraphError> {
+        let mut indices = HashMap::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if indices.insert(node.id, idx).is_some() {
+                return Err(GraphError::DuplicateNode(node.id));
+            }
+        }
+
+        let mut in_degree = vec![0; self.nodes.len()];
+        let mut dependents = vec![Vec::new(); self.nodes.len()];
+        for (from, to) in self.edges.iter() {
+            let from = *indices.get(from).ok_or(GraphError::UnknownNode(*from))?;
+            let to = *indices.get(to).ok_or(GraphError::UnknownNode(*to))?;
+            dependents[from].push(to);
+            in_degree[to] += 1;
+        }
+
+        let mut ready: VecDeque<usize> = (0..self.nodes.len())
+            .filter(|idx| in_degree[*idx] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(idx) = ready.pop_front() {
+            order.push(idx);
+            for dependent in dependents[idx].iter() {
+                in_degree[*dependent] -= 1;
+                if in_degree[*dependent] == 0 {
+                    ready.push_back(*dependent);
+                }
+            }
+        }
+
+        if order.len() < self.nodes.len() {
+            let cycle = (0..self.nodes.len())
+                .filter(|idx| in_degree[*idx] > 0)
+                .map(|idx| self.nodes[idx].id)
+                .collect();
+            return Err(GraphError::Cycle(cycle));
+        }
+
+        self.order = order;
+        Ok(self)
+    }
+}
+
+impl Graph<SyncBody> {
+    pub fn task<F, R>(mut self, id: usize, func: F) -> Self
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: TaskResult,
+    {
+        let body = sync_body(move |_| func());
+        self.nodes.push(Node { id, body });
+        self
+    }
+}
+
+impl Graph<AsyncBody> {
+    pub fn task<F, Fut>(mut self, id: usize, func: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: TaskResult,
+    {
+        let body = async_body(move |_| func());
+        self.nodes.push(Node { id, body });
+        self
+    }
+}
+
+impl<B> Default for Graph<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,456 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::Notify;
+use tracing::info;
+
+use crate::{
+    history::{Execution, History, DEFAULT_HISTORY_CAPACITY},
+    metrics::{Metrics, NoopMetrics},
+    model::Bounds,
+    overlap::{Admit, Overlap, Run, Slots},
+    snapshot::{Snapshot, TaskSnapshot},
+};
+
+/// A description of a task which is registered with a scheduler.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskInfo {
+    pub id: usize,
+    /// The current time between two executions, or `None` if the task does not recur.
+    pub frequency: Option<Duration>,
+    pub paused: bool,
+    /// The number of ticks which were skipped because the task was still running.
+    pub skipped: u64,
+}
+
+/// Why a task stopped waiting for its next tick.
+pub(crate) enum Wake {
+    Tick,
+    Trigger,
+    /// An adaptive task changed its frequency, so the next tick should be moved.
+    Reschedule,
+    Stop,
+}
+
+#[derive(Default)]
+struct ControlState {
+    /// The current delay between two executions, which an adaptive task can change.
+    frequency: Option<Duration>,
+    paused: bool,
+    triggered: bool,
+    rescheduled: bool,
+    stopping: bool,
+    slots: Slots,
+    /// When the next tick is due, which the task keeps up to date for a snapshot.
+    next_tick: Option<SystemTime>,
+    /// The number of executions so far.
+    runs: u64,
+}
+
+/// Lets a running task be paused, resumed, triggered or stopped from outside of the scheduler.
+pub(crate) struct TaskControl {
+    state: Mutex<ControlState>,
+    /// Wakes a thread task, which waits on `state` between executions.
+    condvar: Condvar,
+    /// Wakes an async task which is waiting for its next tick.
+    notify: Notify,
+    /// Wakes everything which waits for the task to be stopped.
+    stopped: Notify,
+    /// How long a stopped task has to wrap up before it is aborted.
+    grace_period: Duration,
+    /// The bounds of an adaptive task, which keeps the frequency it had when it is restored.
+    bounds: Option<Bounds>,
+    /// What the task does, which is exported in a snapshot.
+    spec: Option<Value>,
+}
+
+impl TaskControl {
+    pub(crate) fn new(
+        frequency: Option<Duration>,
+        bounds: Option<Bounds>,
+        overlap: Overlap,
+        grace_period: Duration,
+        spec: Option<Value>,
+    ) -> Self {
+        let state = ControlState {
+            frequency,
+            slots: Slots::new(overlap),
+            ..ControlState::default()
+        };
+        Self {
+            state: Mutex::new(state),
+            condvar: Condvar::new(),
+            notify: Notify::new(),
+            stopped: Notify::new(),
+            grace_period,
+            bounds,
+            spec,
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.state.lock().unwrap().stopping
+    }
+
+    pub(crate) fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    pub(crate) fn frequency(&self) -> Option<Duration> {
+        self.state.lock().unwrap().frequency
+    }
+
+    /// The number of the execution which is starting, counting from 1.
+    pub(crate) fn next_run(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.runs += 1;
+        state.runs
+    }
+
+    /// When the next tick is due, or `None` if the task has not been scheduled yet.
+    pub(crate) fn next_tick(&self) -> Option<SystemTime> {
+        self.state.lock().unwrap().next_tick
+    }
+
+    pub(crate) fn set_next_tick(&self, at: SystemTime) {
+        self.state.lock().unwrap().next_tick = Some(at);
+    }
+
+    /// Pick up the schedule and counters of a task which was exported by another scheduler. An
+    /// adaptive task also keeps the frequency it changed to, within its current bounds.
+    pub(crate) fn restore(&self, saved: &TaskSnapshot) {
+        let mut state = self.state.lock().unwrap();
+        if let (Some(bounds), Some(frequency)) = (self.bounds, saved.frequency) {
+            state.frequency = Some(bounds.clamp(frequency));
+        }
+        state.next_tick = saved.next_tick;
+        state.paused = saved.paused;
+        state.runs = saved.runs;
+        state.slots.skipped = saved.skipped;
+    }
+
+    fn snapshot(&self, id: usize) -> TaskSnapshot {
+        let state = self.state.lock().unwrap();
+        TaskSnapshot {
+            id,
+            spec: self.spec.clone(),
+            frequency: state.frequency,
+            next_tick: state.frequency.and(state.next_tick),
+            paused: state.paused,
+            runs: state.runs,
+            skipped: state.slots.skipped,
+        }
+    }
+
+    /// Change the frequency of an adaptive task, counting from now.
+    pub(crate) fn reschedule(&self, frequency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.frequency = Some(frequency);
+        state.rescheduled = true;
+        self.condvar.notify_all();
+        self.notify.notify_one();
+    }
+
+    /// Decide whether a run starts now, according to the overlap policy of the task.
+    pub(crate) fn admit(&self, run: Run) -> Admit {
+        self.state.lock().unwrap().slots.admit(run)
+    }
+
+    /// Called when an execution is done. Returns the queued run which should start in its place.
+    pub(crate) fn finish(&self) -> Option<Run> {
+        self.state.lock().unwrap().slots.finish()
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.state.lock().unwrap().paused = paused;
+        self.condvar.notify_all();
+    }
+
+    /// Ask for an extra execution. A trigger which arrives during an execution is run after it.
+    /// Returns `true` if the task had not yet picked up the previous trigger, which this one is
+    /// merged with.
+    pub(crate) fn trigger(&self) -> bool {
+        let merged = std::mem::replace(&mut self.state.lock().unwrap().triggered, true);
+        self.condvar.notify_all();
+        self.notify.notify_one();
+        merged
+    }
+
+    /// Ask the task to stop. The scheduler aborts it once the grace period is over.
+    pub(crate) fn stop(&self) {
+        self.state.lock().unwrap().stopping = true;
+        self.condvar.notify_all();
+        self.stopped.notify_waiters();
+    }
+
+    /// Wait until the task is asked to stop.
+    pub(crate) async fn stopped(&self) {
+        loop {
+            // Created before the check, so that a stop in between is not missed.
+            let notified = self.stopped.notified();
+            if self.is_stopping() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Block a thread task until `deadline`, or until it is triggered, rescheduled or stopped.
+    pub(crate) fn wait(&self, deadline: Instant) -> Wake {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopping {
+                return Wake::Stop;
+            }
+            if let Some(wake) = Self::take_wake(&mut state) {
+                return wake;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Wake::Tick;
+            }
+            state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Block a thread task while `blocked` holds for whether it is paused, or until it is
+    /// triggered or stopped. `blocked` is checked again when the task is resumed or the lease
+    /// changes hands, and after `interval` for anything else, like a service window.
+    pub(crate) fn wait_while(&self, interval: Duration, blocked: impl Fn(bool) -> bool) -> Wake {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopping {
+                return Wake::Stop;
+            }
+            if let Some(wake) = Self::take_wake(&mut state) {
+                return wake;
+            }
+            if !blocked(state.paused) {
+                return Wake::Tick;
+            }
+            state = self.condvar.wait_timeout(state, interval).unwrap().0;
+        }
+    }
+
+    /// Wake a thread task which waits in `wait_while`, to check whether it is still blocked.
+    fn recheck(&self) {
+        // Holding the lock makes sure the task is either still checking or already waiting.
+        let _state = self.state.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    /// Wait until an async task is triggered or rescheduled.
+    pub(crate) async fn woken(&self) -> Wake {
+        loop {
+            self.notify.notified().await;
+            if let Some(wake) = Self::take_wake(&mut self.state.lock().unwrap()) {
+                return wake;
+            }
+        }
+    }
+
+    fn take_wake(state: &mut ControlState) -> Option<Wake> {
+        if state.triggered {
+            state.triggered = false;
+            Some(Wake::Trigger)
+        } else if state.rescheduled {
+            state.rescheduled = false;
+            Some(Wake::Reschedule)
+        } else {
+            None
+        }
+    }
+}
+
+/// A cheap, cloneable view into a running scheduler which can be queried from any thread.
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    pub(crate) history: Arc<Mutex<History>>,
+    tasks: Arc<Mutex<HashMap<usize, Arc<TaskControl>>>>,
+    shut_down: Arc<AtomicBool>,
+    /// Whether this instance holds the lease of its scheduler, which it always does without one.
+    leader: Arc<AtomicBool>,
+    /// Wakes the one-shot async tasks which wait for the lease. The thread tasks are woken
+    /// through their `TaskControl`.
+    leader_changed: Arc<Notify>,
+    pub(crate) metrics: Arc<dyn Metrics>,
+    /// Tasks from a snapshot which have not been sent to this scheduler yet.
+    restored: Arc<Mutex<HashMap<usize, TaskSnapshot>>>,
+}
+
+impl SchedulerHandle {
+    pub(crate) fn new(history_capacity: usize, metrics: Arc<dyn Metrics>) -> Self {
+        Self {
+            history: Arc::new(Mutex::new(History::new(history_capacity))),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            shut_down: Arc::new(AtomicBool::new(false)),
+            leader: Arc::new(AtomicBool::new(true)),
+            leader_changed: Arc::new(Notify::new()),
+            metrics,
+            restored: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The most recent executions of a task, oldest first.
+    pub fn history(&self, id: usize) -> Vec<Execution> {
+        self.history.lock().unwrap().get(id)
+    }
+
+    /// Every registered task, ordered by id.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<TaskInfo> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, control)| {
+                let state = control.state.lock().unwrap();
+                TaskInfo {
+                    id: *id,
+                    frequency: state.frequency,
+                    paused: state.paused,
+                    skipped: state.slots.skipped,
+                }
+            })
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+        tasks
+    }
+
+    /// Export the schedule of every registered task, which a new scheduler can be given with
+    /// `Scheduler::restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut tasks: Vec<TaskSnapshot> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, control)| control.snapshot(*id))
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+        Snapshot {
+            taken_at: SystemTime::now(),
+            tasks,
+        }
+    }
+
+    /// Skip the scheduled executions of a task until it is resumed. Returns `false` if the task
+    /// is not registered.
+    pub fn pause(&self, id: usize) -> bool {
+        self.with_control(id, |control| control.set_paused(true))
+    }
+
+    pub fn resume(&self, id: usize) -> bool {
+        self.with_control(id, |control| control.set_paused(false))
+    }
+
+    /// Run a task once right away, without changing its schedule. Returns `false` if the task is
+    /// not registered.
+    pub fn trigger(&self, id: usize) -> bool {
+        self.with_control(id, |control| {
+            if control.trigger() {
+                info!(id, "Merged a trigger with the one which is pending");
+                self.metrics.trigger_merged(id);
+            }
+        })
+    }
+
+    /// Stop every task. The scheduler ignores any task created after this.
+    pub fn shutdown(&self) {
+        self.shut_down.store(true, Ordering::SeqCst);
+        for (_, control) in self.tasks.lock().unwrap().drain() {
+            control.stop();
+        }
+        self.metrics.tasks_registered(0);
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if this instance runs the scheduled executions of its tasks.
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_leader(&self, leader: bool) {
+        if self.leader.swap(leader, Ordering::SeqCst) != leader {
+            self.leader_changed.notify_waiters();
+            for control in self.tasks.lock().unwrap().values() {
+                control.recheck();
+            }
+        }
+    }
+
+    /// Wait until this instance holds the lease.
+    pub(crate) async fn leading(&self) {
+        loop {
+            // Created before the check, so that a change in between still wakes it.
+            let changed = self.leader_changed.notified();
+            if self.is_leader() {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Keep the tasks of `snapshot` until they are created.
+    pub(crate) fn restore(&self, snapshot: Snapshot) {
+        let mut restored = self.restored.lock().unwrap();
+        restored.extend(snapshot.tasks.into_iter().map(|task| (task.id, task)));
+    }
+
+    /// Register a task, which picks up where it left off if it is in a restored snapshot.
+    pub(crate) fn register(&self, id: usize, control: Arc<TaskControl>) {
+        if let Some(saved) = self.restored.lock().unwrap().remove(&id) {
+            control.restore(&saved);
+        }
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.insert(id, control);
+        self.metrics.tasks_registered(tasks.len());
+    }
+
+    pub(crate) fn unregister(&self, id: usize) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.remove(&id);
+        self.metrics.tasks_registered(tasks.len());
+    }
+
+    /// Keep an execution in the history of a task, and report it to the metrics.
+    pub(crate) fn record(&self, id: usize, execution: Execution) {
+        self.metrics.execution_finished(id, &execution);
+        self.history.lock().unwrap().record(id, execution);
+    }
+
+    fn with_control<F>(&self, id: usize, f: F) -> bool
+    where
+        F: FnOnce(&TaskControl),
+    {
+        match self.tasks.lock().unwrap().get(&id) {
+            Some(control) => {
+                f(control);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A handle which is not attached to a scheduler, which is mostly useful in tests.
+impl Default for SchedulerHandle {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_CAPACITY, Arc::new(NoopMetrics))
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+use crate::process::ProcessOutput;
+
+pub(crate) const DEFAULT_HISTORY_CAPACITY: usize = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+    Panic,
+    /// A dependency in a `Graph` did not succeed, so the body was not run.
+    Skipped,
+}
+
+/// A record of a single run of a task body.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Execution {
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+    /// The captured output when the task body is a `ProcessTask`.
+    pub output: Option<ProcessOutput>,
+}
+
+impl Execution {
+    pub(crate) fn new(
+        started_at: SystemTime,
+        duration: Duration,
+        result: Result<(), String>,
+    ) -> Self {
+        let (outcome, error) = match result {
+            Ok(()) => (Outcome::Success, None),
+            Err(e) => (Outcome::Failure, Some(e)),
+        };
+
+        Self {
+            started_at,
+            duration,
+            outcome,
+            error,
+            output: None,
+        }
+    }
+
+    pub(crate) fn skipped(dependency: usize) -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            duration: Duration::from_millis(0),
+            outcome: Outcome::Skipped,
+            error: Some(format!("dependency {} did not succeed", dependency)),
+            output: None,
+        }
+    }
+
+    pub(crate) fn panicked(started_at: SystemTime, duration: Duration, message: String) -> Self {
+        Self {
+            started_at,
+            duration,
+            outcome: Outcome::Panic,
+            error: Some(message),
+            output: None,
+        }
+    }
+}
+
+/// Keeps the most recent executions of each task, dropping the oldest once `capacity` is reached.
+pub(crate) struct History {
+    capacity: usize,
+    runs: HashMap<usize, VecDeque<Execution>>,
+    /// The nodes of each `Graph` task, whose history is removed along with the task.
+    nodes: HashMap<usize, Vec<usize>>,
+}
+
+impl History {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            runs: HashMap::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn add_nodes(&mut self, id: usize, node_ids: Vec<usize>) {
+        self.nodes.insert(id, node_ids);
+    }
+
+    pub(crate) fn record(&mut self, id: usize, execution: Execution) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let runs = self.runs.entry(id).or_default();
+        if runs.len() == self.capacity {
+            runs.pop_front();
+        }
+        runs.push_back(execution);
+    }
+
+    /// Returns the executions of a task, oldest first.
+    pub(crate) fn get(&self, id: usize) -> Vec<Execution> {
+        self.runs
+            .get(&id)
+            .map(|runs| runs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) {
+        self.runs.remove(&id);
+        for node_id in self.nodes.remove(&id).unwrap_or_default() {
+            self.runs.remove(&node_id);
+        }
+    }
+}
+
+/// Extract the message from the payload of a panic, which is usually a `&str` or a `String`.
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Decides which of several scheduler instances runs the scheduled executions. Every instance
+/// keeps its tasks registered, but only the one holding the lease runs their ticks. Another
+/// instance takes over once the lease is released or expires.
+pub trait Lease: Send {
+    /// Take the lease, or renew it if this instance holds it already. Returns `true` while this
+    /// instance holds the lease.
+    fn acquire(&mut self) -> io::Result<bool>;
+
+    /// Give up the lease, so that another instance can take it right away.
+    fn release(&mut self) -> io::Result<()>;
+
+    /// How often the scheduler calls `acquire`, which must be well within the expiry of the lease.
+    fn renew_interval(&self) -> Duration;
+}
+
+/// A lease kept in a file on a disk which every instance can reach. The file names the holder and
+/// when the lease expires, and an advisory lock on it keeps two instances from taking it at once.
+#[derive(Clone, Debug)]
+pub struct FileLease {
+    path: PathBuf,
+    holder: String,
+    ttl: Duration,
+}
+
+impl FileLease {
+    /// A lease at `path` which expires `ttl` after it was last renewed.
+    pub fn new(path: impl Into<PathBuf>, ttl: Duration) -> Self {
+        let host = fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|host| host.trim().to_string())
+            .unwrap_or_else(|_| "localhost".to_string());
+        Self {
+            path: path.into(),
+            holder: format!("{}:{}", host, process::id()),
+            ttl,
+        }
+    }
+
+    /// The name this instance writes into the file. The default is the host name and process id.
+    pub fn holder(mut self, holder: &str) -> Self {
+        self.holder = holder.to_string();
+        self
+    }
+
+    /// Open the file and lock it until it is closed.
+    fn open(&self) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// Returns `true` if someone else holds a lease which has not expired.
+    fn held_by_other(&self, file: &mut File) -> io::Result<bool> {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let mut lines = contents.lines();
+        let (holder, expires_at) = match (lines.next(), lines.next()) {
+            (Some(holder), Some(expires_at)) => (holder, expires_at),
+            // An empty or partly written file is free to take.
+            _ => return Ok(false),
+        };
+        let expires_at = expires_at.parse::<u64>().unwrap_or_default();
+        Ok(holder != self.holder && expires_at > millis(SystemTime::now()))
+    }
+}
+
+impl Lease for FileLease {
+    fn acquire(&mut self) -> io::Result<bool> {
+        let mut file = self.open()?;
+        if self.held_by_other(&mut file)? {
+            return Ok(false);
+        }
+
+        let expires_at = millis(SystemTime::now() + self.ttl);
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}\n{}\n", self.holder, expires_at)?;
+        file.sync_all()?;
+        Ok(true)
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        let mut file = self.open()?;
+        if !self.held_by_other(&mut file)? {
+            file.set_len(0)?;
+        }
+        Ok(())
+    }
+
+    fn renew_interval(&self) -> Duration {
+        self.ttl / 3
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -1,7 +1,36 @@
+mod admission;
 mod async_scheduler;
+mod context;
+pub mod control;
+mod graph;
+mod handle;
+mod history;
+mod lease;
+mod metrics;
 mod model;
+mod overlap;
+mod process;
 mod scheduler;
+mod snapshot;
 mod thread_scheduler;
+mod window;
 
-pub use model::{AsyncTask, SyncTask, Task};
+pub use admission::{channel, AdmissionError, Limits, TaskSender, Ticket};
+pub use context::TaskContext;
+pub use graph::{AsyncGraph, Graph, GraphError, SyncGraph};
+pub use handle::{SchedulerHandle, TaskInfo};
+pub use history::{Execution, Outcome};
+pub use lease::{FileLease, Lease};
+pub use metrics::{InMemoryMetrics, Metrics, NoopMetrics};
+pub use model::{
+    AsyncBody, AsyncFunc, AsyncTask, Bounds, BoundsError, Operation, Report, SyncBody, SyncFunc,
+    SyncTask, Task, TaskFuture, TaskResult,
+};
+pub use overlap::Overlap;
+pub use process::{ProcessOutput, ProcessTask};
 pub use scheduler::Scheduler;
+pub use snapshot::{Snapshot, TaskSnapshot};
+pub use window::{ServiceWindow, WindowError};
+
+/// The IANA time zones, which are compiled into the crate.
+pub use chrono_tz::Tz;
@@ -0,0 +1,252 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+use crate::history::{Execution, Outcome};
+
+/// The upper bounds, in seconds, of the buckets of the execution duration and schedule lag
+/// histograms.
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Receives measurements from a scheduler. Every method does nothing by default, so an
+/// implementation only needs the ones it cares about.
+pub trait Metrics: Send + Sync {
+    /// The number of tasks registered with the scheduler changed.
+    fn tasks_registered(&self, _count: usize) {}
+
+    /// A task started an execution `lag` after it was due.
+    fn execution_started(&self, _id: usize, _lag: Duration) {}
+
+    /// An execution of a task, or of a node in its graph, was recorded.
+    fn execution_finished(&self, _id: usize, _execution: &Execution) {}
+
+    /// A tick was dropped because the task was still running.
+    fn tick_skipped(&self, _id: usize) {}
+
+    /// A trigger was merged with a run, or a trigger, which was already waiting for the task.
+    fn trigger_merged(&self, _id: usize) {}
+
+    /// A `TaskSender` queued a command for the scheduler.
+    fn command_queued(&self) {}
+
+    /// The scheduler took a command off its queue.
+    fn command_received(&self) {}
+}
+
+/// Metrics which are thrown away, which is what a scheduler uses unless it is given others.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+/// Metrics which are kept in memory and can be rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct InMemoryMetrics {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    registered: usize,
+    queued: u64,
+    received: u64,
+    tasks: BTreeMap<usize, TaskMetrics>,
+}
+
+#[derive(Debug, Default)]
+struct TaskMetrics {
+    /// The number of executions for each outcome.
+    executions: BTreeMap<&'static str, u64>,
+    skipped_ticks: u64,
+    merged_triggers: u64,
+    duration: Histogram,
+    lag: Histogram,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// The number of observations in each bucket, which are made cumulative when rendered.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        if let Some(idx) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[idx] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, id: usize) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{task=\"{id}\",le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{task=\"{id}\",le=\"+Inf\"}} {}",
+            self.count
+        );
+        let _ = writeln!(out, "{name}_sum{{task=\"{id}\"}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{task=\"{id}\"}} {}", self.count);
+    }
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "tulsa_registered_tasks",
+            "gauge",
+            "Tasks registered with the scheduler.",
+        );
+        let _ = writeln!(out, "tulsa_registered_tasks {}", state.registered);
+
+        header(
+            &mut out,
+            "tulsa_queue_depth",
+            "gauge",
+            "Commands waiting for the scheduler.",
+        );
+        let depth = state.queued.saturating_sub(state.received);
+        let _ = writeln!(out, "tulsa_queue_depth {}", depth);
+
+        header(
+            &mut out,
+            "tulsa_executions_total",
+            "counter",
+            "Executions by outcome.",
+        );
+        for (id, task) in state.tasks.iter() {
+            for (outcome, count) in task.executions.iter() {
+                let _ = writeln!(
+                    out,
+                    "tulsa_executions_total{{task=\"{id}\",outcome=\"{outcome}\"}} {count}"
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "tulsa_skipped_ticks_total",
+            "counter",
+            "Ticks dropped because the task was still running.",
+        );
+        for (id, task) in state.tasks.iter() {
+            let _ = writeln!(
+                out,
+                "tulsa_skipped_ticks_total{{task=\"{id}\"}} {}",
+                task.skipped_ticks
+            );
+        }
+
+        header(
+            &mut out,
+            "tulsa_merged_triggers_total",
+            "counter",
+            "Triggers merged with a run or trigger which was already waiting.",
+        );
+        for (id, task) in state.tasks.iter() {
+            let _ = writeln!(
+                out,
+                "tulsa_merged_triggers_total{{task=\"{id}\"}} {}",
+                task.merged_triggers
+            );
+        }
+
+        let name = "tulsa_execution_duration_seconds";
+        header(&mut out, name, "histogram", "How long each execution took.");
+        for (id, task) in state.tasks.iter() {
+            task.duration.render(&mut out, name, *id);
+        }
+
+        let name = "tulsa_schedule_lag_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "How late each execution started.",
+        );
+        for (id, task) in state.tasks.iter() {
+            task.lag.render(&mut out, name, *id);
+        }
+
+        out
+    }
+
+    fn with_task<F>(&self, id: usize, f: F)
+    where
+        F: FnOnce(&mut TaskMetrics),
+    {
+        f(self.state.lock().unwrap().tasks.entry(id).or_default());
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn tasks_registered(&self, count: usize) {
+        self.state.lock().unwrap().registered = count;
+    }
+
+    fn execution_started(&self, id: usize, lag: Duration) {
+        self.with_task(id, |task| task.lag.observe(lag));
+    }
+
+    fn execution_finished(&self, id: usize, execution: &Execution) {
+        self.with_task(id, |task| {
+            *task
+                .executions
+                .entry(outcome_label(&execution.outcome))
+                .or_default() += 1;
+            // A node which was skipped never ran, so it has no duration.
+            if execution.outcome != Outcome::Skipped {
+                task.duration.observe(execution.duration);
+            }
+        });
+    }
+
+    fn tick_skipped(&self, id: usize) {
+        self.with_task(id, |task| task.skipped_ticks += 1);
+    }
+
+    fn trigger_merged(&self, id: usize) {
+        self.with_task(id, |task| task.merged_triggers += 1);
+    }
+
+    fn command_queued(&self) {
+        self.state.lock().unwrap().queued += 1;
+    }
+
+    fn command_received(&self) {
+        self.state.lock().unwrap().received += 1;
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn outcome_label(outcome: &Outcome) -> &'static str {
+    match outcome {
+        Outcome::Success => "success",
+        Outcome::Failure => "failure",
+        Outcome::Panic => "panic",
+        Outcome::Skipped => "skipped",
+    }
+}
@@ -1,45 +1,405 @@
-use std::{future::Future, pin::Pin, time::Duration};
+use serde_json::Value;
+use std::{fmt, future::Future, pin::Pin, time::Duration};
+use tracing::{info_span, Span};
 
+use crate::{
+    admission::Ticket,
+    context::TaskContext,
+    graph::{AsyncGraph, GraphError, SyncGraph},
+    overlap::Overlap,
+    process::ProcessTask,
+    window::ServiceWindow,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Operation {
     Create,
     Update,
     Delete,
+    /// Run a registered task once right away, after any execution which is in progress.
+    Trigger,
+}
+
+/// Converts the value returned by a task body into the result of an execution. This lets task
+/// bodies return either `()` or a `Result` whose error is recorded in the task history.
+///
+/// A body of an adaptive task can also return a `Duration`, or a `Result` of one, to suggest the
+/// delay before its next scheduled execution.
+pub trait TaskResult {
+    fn into_result(self) -> Result<(), String>;
+
+    fn next_delay(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl TaskResult for () {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E> TaskResult for Result<(), E>
+where
+    E: std::fmt::Display,
+{
+    fn into_result(self) -> Result<(), String> {
+        self.map_err(|e| e.to_string())
+    }
+}
+
+impl TaskResult for Duration {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn next_delay(&self) -> Option<Duration> {
+        Some(*self)
+    }
+}
+
+impl<E> TaskResult for Result<Duration, E>
+where
+    E: std::fmt::Display,
+{
+    fn into_result(self) -> Result<(), String> {
+        self.map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    fn next_delay(&self) -> Option<Duration> {
+        self.as_ref().ok().copied()
+    }
+}
+
+/// What a single run of a task body returned to the scheduler.
+pub struct Report {
+    pub(crate) result: Result<(), String>,
+    pub(crate) next_delay: Option<Duration>,
+}
+
+impl Report {
+    pub(crate) fn new<R>(value: R) -> Self
+    where
+        R: TaskResult,
+    {
+        let next_delay = value.next_delay();
+        Self {
+            result: value.into_result(),
+            next_delay,
+        }
+    }
+}
+
+pub type TaskFuture = Pin<Box<dyn Future<Output = Report> + Send>>;
+pub type SyncBody = Pin<Box<dyn Fn(TaskContext) -> Report + Send + Sync>>;
+pub type AsyncBody = Pin<Box<dyn Fn(TaskContext) -> TaskFuture + Send + Sync>>;
+
+pub(crate) fn sync_body<F, R>(func: F) -> SyncBody
+where
+    F: Fn(TaskContext) -> R + Send + Sync + 'static,
+    R: TaskResult,
+{
+    Box::pin(move |context| Report::new(func(context)))
+}
+
+pub(crate) fn async_body<F, Fut>(func: F) -> AsyncBody
+where
+    F: Fn(TaskContext) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: TaskResult,
+{
+    Box::pin(move |context| -> TaskFuture {
+        let future = func(context);
+        Box::pin(async move { Report::new(future.await) })
+    })
+}
+
+/// A span which covers everything a task does.
+fn task_span(
+    id: usize,
+    frequency: Option<Duration>,
+    tags: &[(String, String)],
+    parent: Option<&Span>,
+) -> Span {
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    info_span!(
+        parent: parent.and_then(Span::id),
+        "task",
+        id,
+        frequency = ?frequency,
+        tags = %tags.join(","),
+    )
+}
+
+/// The shortest delay an adaptive task can get between two executions.
+const MIN_DELAY: Duration = Duration::from_millis(1);
+
+/// The shortest and longest delay an adaptive task may ask for between two executions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+/// Used to indicate the bounds of an adaptive task cannot be used.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BoundsError {
+    /// The minimum delay is zero, which would let the task run without a pause.
+    ZeroMin,
+    /// The minimum delay is greater than the maximum.
+    MinAboveMax { min: Duration, max: Duration },
+}
+
+impl fmt::Display for BoundsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoundsError::ZeroMin => write!(f, "the minimum delay is zero"),
+            BoundsError::MinAboveMax { min, max } => write!(
+                f,
+                "the minimum delay {:?} is greater than the maximum {:?}",
+                min, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BoundsError {}
+
+impl Bounds {
+    /// Bounds which an adaptive task can use, which are rejected if `min` is zero or greater
+    /// than `max`.
+    pub fn new(min: Duration, max: Duration) -> Result<Self, BoundsError> {
+        if min.is_zero() {
+            return Err(BoundsError::ZeroMin);
+        }
+        if min > max {
+            return Err(BoundsError::MinAboveMax { min, max });
+        }
+        Ok(Self { min, max })
+    }
+
+    /// Keep `delay` within the bounds. It is never zero, even with a zero `min`, since a task
+    /// cannot be rescheduled without a delay.
+    pub(crate) fn clamp(&self, delay: Duration) -> Duration {
+        delay.clamp(self.min, self.max).max(MIN_DELAY)
+    }
+}
+
+pub enum AsyncFunc {
+    /// A future which is run to completion a single time.
+    Once(Pin<Box<dyn Future<Output = Report> + Send + Sync>>),
+    /// A factory which produces a new future on every tick of the task frequency.
+    Recurring(AsyncBody),
+    /// A graph of factories which is run in topological order on every tick of the task
+    /// frequency, or a single time if `once` is set.
+    Graph { graph: AsyncGraph, once: bool },
+    /// An external command which is run on every tick of the task frequency.
+    Process(ProcessTask),
+}
+
+pub enum SyncFunc {
+    /// A body which is run on every tick of the task frequency.
+    Recurring(SyncBody),
+    /// A graph of bodies which is run in topological order on every tick of the task frequency,
+    /// or a single time if `once` is set.
+    Graph { graph: SyncGraph, once: bool },
+    /// An external command which is run on every tick of the task frequency.
+    Process(ProcessTask),
 }
 
 pub struct AsyncTask {
     pub id: usize,
-    pub func: Pin<Box<dyn Future<Output = ()> + Send + Sync>>,
+    pub frequency: Duration,
+    pub func: AsyncFunc,
     pub op: Operation,
+    /// Set by `adaptive`, which lets the body change the delay before its next execution.
+    pub bounds: Option<Bounds>,
+    pub overlap: Overlap,
+    /// Set by `window`, which limits the scheduled executions to part of each service day.
+    pub window: Option<ServiceWindow>,
+    /// Names and values which are recorded on the span of the task.
+    pub tags: Vec<(String, String)>,
+    /// The span which the span of the task is a child of, set by `parent_span`.
+    pub parent_span: Option<Span>,
+    /// Set by `describe`, which says what the task does in a snapshot.
+    pub spec: Option<Value>,
+    /// Given to a one-shot task when it is admitted, and held until it has run.
+    pub ticket: Option<Ticket>,
 }
 
 impl AsyncTask {
-    pub fn new<F>(id: usize, func: F) -> Self
-    where
-        F: Future<Output = ()> + Send + Sync + 'static,
-    {
+    /// The task every constructor starts from, with the defaults of every builder.
+    fn base(id: usize, frequency: Duration, func: AsyncFunc) -> Self {
         Self {
             id,
-            func: Box::pin(func),
+            frequency,
+            func,
             op: Operation::Create,
+            bounds: None,
+            overlap: Overlap::default(),
+            window: None,
+            tags: Vec::new(),
+            parent_span: None,
+            spec: None,
+            ticket: None,
         }
     }
 
+    pub fn new<F>(id: usize, func: F) -> Self
+    where
+        F: Future + Send + Sync + 'static,
+        F::Output: TaskResult,
+    {
+        Self::base(
+            id,
+            Duration::from_millis(0),
+            AsyncFunc::Once(Box::pin(async move { Report::new(func.await) })),
+        )
+    }
+
     pub fn update<F>(id: usize, func: F) -> Self
     where
-        F: Future<Output = ()> + Send + Sync + 'static,
+        F: Future + Send + Sync + 'static,
+        F::Output: TaskResult,
     {
         Self {
-            id,
-            func: Box::pin(func),
             op: Operation::Update,
+            ..Self::new(id, func)
         }
     }
 
-    pub fn stop(id: usize) -> Self {
+    /// Create a task whose body is a new future, produced by `func`, on every tick of `frequency`.
+    /// Unlike [`AsyncTask::new`], the scheduler drives the recurrence and can therefore record
+    /// each execution. The scheduler ignores the task if `frequency` is zero.
+    pub fn recurring<F, Fut>(id: usize, frequency: Duration, func: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: TaskResult,
+    {
+        Self::recurring_with_context(id, frequency, move |_| func())
+    }
+
+    /// Like [`AsyncTask::recurring`], but the body is given a `TaskContext`, which tells it when
+    /// the task is being stopped.
+    pub fn recurring_with_context<F, Fut>(id: usize, frequency: Duration, func: F) -> Self
+    where
+        F: Fn(TaskContext) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: TaskResult,
+    {
+        Self::base(id, frequency, AsyncFunc::Recurring(async_body(func)))
+    }
+
+    pub fn update_recurring<F, Fut>(id: usize, frequency: Duration, func: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: TaskResult,
+    {
         Self {
+            op: Operation::Update,
+            ..Self::recurring(id, frequency, func)
+        }
+    }
+
+    /// Create a task which runs `graph` on every tick of `frequency`. The graph is rejected if
+    /// it contains a cycle.
+    pub fn graph(id: usize, frequency: Duration, graph: AsyncGraph) -> Result<Self, GraphError> {
+        Ok(Self::base(
+            id,
+            frequency,
+            AsyncFunc::Graph {
+                graph: graph.validate()?,
+                once: false,
+            },
+        ))
+    }
+
+    /// Create a task which runs `graph` a single time. The graph is rejected if it contains a
+    /// cycle.
+    pub fn graph_once(id: usize, graph: AsyncGraph) -> Result<Self, GraphError> {
+        Ok(Self::base(
             id,
-            func: Box::pin(async {}),
+            Duration::from_millis(0),
+            AsyncFunc::Graph {
+                graph: graph.validate()?,
+                once: true,
+            },
+        ))
+    }
+
+    /// Create a task which runs an external command on every tick of `frequency`. The command
+    /// is killed if the task is stopped while it is running.
+    pub fn process(id: usize, frequency: Duration, process: ProcessTask) -> Self {
+        Self::base(id, frequency, AsyncFunc::Process(process))
+    }
+
+    /// Let the body suggest the delay before its next execution by returning a `Duration`. The
+    /// suggestion is kept between `min` and `max`, and ignored for a triggered execution.
+    ///
+    /// Fails if `min` is zero or greater than `max`.
+    pub fn adaptive(mut self, min: Duration, max: Duration) -> Result<Self, BoundsError> {
+        self.bounds = Some(Bounds::new(min, max)?);
+        Ok(self)
+    }
+
+    /// Choose what happens to a tick which arrives while the task is still running. The default
+    /// is `Overlap::Queue`.
+    pub fn overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Only run the scheduled executions while `window` is open.
+    pub fn window(mut self, window: ServiceWindow) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Record `key` and `value` on the span of the task.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Make the span of the task a child of `span`, usually `Span::current()` of the sender, so
+    /// that the executions of the task are part of the same trace.
+    pub fn parent_span(mut self, span: Span) -> Self {
+        self.parent_span = Some(span);
+        self
+    }
+
+    /// Say what the task does, so that it can be rebuilt from a snapshot by another process.
+    pub fn describe(mut self, spec: Value) -> Self {
+        self.spec = Some(spec);
+        self
+    }
+
+    pub(crate) fn span(&self) -> Span {
+        task_span(
+            self.id,
+            self.frequency(),
+            &self.tags,
+            self.parent_span.as_ref(),
+        )
+    }
+
+    pub fn stop(id: usize) -> Self {
+        Self {
             op: Operation::Delete,
+            ..Self::new(id, async {})
+        }
+    }
+
+    /// Run the body of a registered task once, without changing its schedule.
+    pub fn trigger(id: usize) -> Self {
+        Self {
+            op: Operation::Trigger,
+            ..Self::new(id, async {})
         }
     }
 }
@@ -47,47 +407,227 @@ impl AsyncTask {
 pub struct SyncTask {
     pub id: usize,
     pub frequency: Duration,
-    pub func: Pin<Box<dyn Fn() + Send + Sync>>,
+    pub func: SyncFunc,
     pub op: Operation,
+    /// Set by `adaptive`, which lets the body change the delay before its next execution.
+    pub bounds: Option<Bounds>,
+    pub overlap: Overlap,
+    /// Set by `window`, which limits the scheduled executions to part of each service day.
+    pub window: Option<ServiceWindow>,
+    /// Names and values which are recorded on the span of the task.
+    pub tags: Vec<(String, String)>,
+    /// The span which the span of the task is a child of, set by `parent_span`.
+    pub parent_span: Option<Span>,
+    /// Set by `describe`, which says what the task does in a snapshot.
+    pub spec: Option<Value>,
+    /// Given to a one-shot task when it is admitted, and held until it has run.
+    pub ticket: Option<Ticket>,
 }
 
 impl SyncTask {
-    pub fn new<F>(id: usize, frequency: Duration, func: F) -> Self
-    where
-        F: Fn() + Send + Sync + 'static,
-    {
+    /// The task every constructor starts from, with the defaults of every builder.
+    fn base(id: usize, frequency: Duration, func: SyncFunc) -> Self {
         Self {
             id,
             frequency,
-            func: Box::pin(func),
+            func,
             op: Operation::Create,
+            bounds: None,
+            overlap: Overlap::default(),
+            window: None,
+            tags: Vec::new(),
+            parent_span: None,
+            spec: None,
+            ticket: None,
         }
     }
 
-    pub fn update<F>(id: usize, frequency: Duration, func: F) -> Self
+    pub fn new<F, R>(id: usize, frequency: Duration, func: F) -> Self
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: TaskResult,
+    {
+        Self::with_context(id, frequency, move |_| func())
+    }
+
+    /// Like [`SyncTask::new`], but the body is given a `TaskContext`, which tells it when the
+    /// task is being stopped.
+    pub fn with_context<F, R>(id: usize, frequency: Duration, func: F) -> Self
+    where
+        F: Fn(TaskContext) -> R + Send + Sync + 'static,
+        R: TaskResult,
+    {
+        Self::base(id, frequency, SyncFunc::Recurring(sync_body(func)))
+    }
+
+    pub fn update<F, R>(id: usize, frequency: Duration, func: F) -> Self
     where
-        F: Fn() + Send + Sync + 'static,
+        F: Fn() -> R + Send + Sync + 'static,
+        R: TaskResult,
     {
         Self {
-            id,
-            frequency,
-            func: Box::pin(func),
             op: Operation::Update,
+            ..Self::new(id, frequency, func)
         }
     }
 
+    /// Create a task which runs `graph` on every tick of `frequency`. The graph is rejected if
+    /// it contains a cycle.
+    pub fn graph(id: usize, frequency: Duration, graph: SyncGraph) -> Result<Self, GraphError> {
+        Ok(Self::base(
+            id,
+            frequency,
+            SyncFunc::Graph {
+                graph: graph.validate()?,
+                once: false,
+            },
+        ))
+    }
+
+    /// Create a task which runs `graph` a single time. The graph is rejected if it contains a
+    /// cycle.
+    pub fn graph_once(id: usize, graph: SyncGraph) -> Result<Self, GraphError> {
+        Ok(Self::base(
+            id,
+            Duration::from_millis(0),
+            SyncFunc::Graph {
+                graph: graph.validate()?,
+                once: true,
+            },
+        ))
+    }
+
+    /// Create a task which runs an external command on every tick of `frequency`. The command
+    /// is killed if the task is stopped while it is running.
+    pub fn process(id: usize, frequency: Duration, process: ProcessTask) -> Self {
+        Self::base(id, frequency, SyncFunc::Process(process))
+    }
+
+    /// Let the body suggest the delay before its next execution by returning a `Duration`. The
+    /// suggestion is kept between `min` and `max`, and ignored for a triggered execution.
+    ///
+    /// Fails if `min` is zero or greater than `max`.
+    pub fn adaptive(mut self, min: Duration, max: Duration) -> Result<Self, BoundsError> {
+        self.bounds = Some(Bounds::new(min, max)?);
+        Ok(self)
+    }
+
+    /// Choose what happens to a tick which arrives while the task is still running. The default
+    /// is `Overlap::Queue`.
+    pub fn overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Only run the scheduled executions while `window` is open.
+    pub fn window(mut self, window: ServiceWindow) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Record `key` and `value` on the span of the task.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Make the span of the task a child of `span`, usually `Span::current()` of the sender, so
+    /// that the executions of the task are part of the same trace.
+    pub fn parent_span(mut self, span: Span) -> Self {
+        self.parent_span = Some(span);
+        self
+    }
+
+    /// Say what the task does, so that it can be rebuilt from a snapshot by another process.
+    pub fn describe(mut self, spec: Value) -> Self {
+        self.spec = Some(spec);
+        self
+    }
+
+    pub(crate) fn span(&self) -> Span {
+        task_span(
+            self.id,
+            self.frequency(),
+            &self.tags,
+            self.parent_span.as_ref(),
+        )
+    }
+
     pub fn stop(id: usize) -> Self {
         Self {
-            id,
-            frequency: Duration::from_millis(0),
-            func: Box::pin(|| {}),
             op: Operation::Delete,
+            ..Self::new(id, Duration::from_millis(0), || {})
+        }
+    }
+
+    /// Run the body of a registered task once, without changing its schedule.
+    pub fn trigger(id: usize) -> Self {
+        Self {
+            op: Operation::Trigger,
+            ..Self::new(id, Duration::from_millis(0), || {})
+        }
+    }
+}
+
+/// A trait which allows for trait bounds to only allow `AsyncTask` or `SyncTask`, and lets a
+/// client inspect a task before it is sent to a scheduler.
+pub trait Task {
+    fn id(&self) -> usize;
+    fn op(&self) -> Operation;
+    /// The time between two executions, or `None` if the task does not recur.
+    fn frequency(&self) -> Option<Duration>;
+    /// The bounds of an adaptive task, within which its frequency can change.
+    fn bounds(&self) -> Option<Bounds>;
+    /// Keep the `Ticket` of an admitted one-shot task until the task has run.
+    fn hold(&mut self, ticket: Ticket);
+}
+
+impl Task for AsyncTask {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn op(&self) -> Operation {
+        self.op
+    }
+
+    fn frequency(&self) -> Option<Duration> {
+        match self.func {
+            AsyncFunc::Once(_) | AsyncFunc::Graph { once: true, .. } => None,
+            _ => Some(self.frequency),
         }
     }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.bounds
+    }
+
+    fn hold(&mut self, ticket: Ticket) {
+        self.ticket = Some(ticket);
+    }
 }
 
-/// An empty trait which allows for trait bounds to only allow `AsyncTask` or `SyncTask`.
-pub trait Task {}
+impl Task for SyncTask {
+    fn id(&self) -> usize {
+        self.id
+    }
+
+    fn op(&self) -> Operation {
+        self.op
+    }
 
-impl Task for AsyncTask {}
-impl Task for SyncTask {}
+    fn frequency(&self) -> Option<Duration> {
+        match self.func {
+            SyncFunc::Graph { once: true, .. } => None,
+            _ => Some(self.frequency),
+        }
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.bounds
+    }
+
+    fn hold(&mut self, ticket: Ticket) {
+        self.ticket = Some(ticket);
+    }
+}
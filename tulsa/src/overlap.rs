@@ -0,0 +1,107 @@
+use std::time::SystemTime;
+
+/// What a recurring task does with a tick which arrives while it is still running.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overlap {
+    /// Skip the tick.
+    Skip,
+    /// Run once more as soon as the current execution is done, and skip any further ticks.
+    #[default]
+    Queue,
+    /// Run up to this many executions at once, and skip a tick while all of them are busy.
+    Concurrent(usize),
+}
+
+impl Overlap {
+    /// The number of executions which can run at once.
+    fn limit(&self) -> usize {
+        match self {
+            Overlap::Skip | Overlap::Queue => 1,
+            Overlap::Concurrent(limit) => (*limit).max(1),
+        }
+    }
+}
+
+/// Whether a run came from a tick of the schedule or from a trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RunKind {
+    Tick,
+    Trigger,
+}
+
+/// A run which is due, along with when it was due.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Run {
+    pub(crate) kind: RunKind,
+    pub(crate) scheduled_at: SystemTime,
+}
+
+impl Run {
+    pub(crate) fn new(kind: RunKind, scheduled_at: SystemTime) -> Self {
+        Self { kind, scheduled_at }
+    }
+}
+
+/// What happens to a run which asks for a slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Admit {
+    /// The run should start right away.
+    Start,
+    /// The run waits for an execution in progress.
+    Queue,
+    /// The trigger is merged with a run which is already waiting.
+    Merge,
+    /// The tick is dropped because of the overlap policy.
+    Skip,
+}
+
+/// Tracks the executions of a task which are running or waiting to run.
+#[derive(Debug, Default)]
+pub(crate) struct Slots {
+    overlap: Overlap,
+    running: usize,
+    queued: Option<Run>,
+    /// The number of ticks which were dropped because of `overlap`.
+    pub(crate) skipped: u64,
+}
+
+impl Slots {
+    pub(crate) fn new(overlap: Overlap) -> Self {
+        Self {
+            overlap,
+            ..Self::default()
+        }
+    }
+
+    /// Decide whether a run starts right away, or is queued or skipped. A trigger which arrives
+    /// during an execution is always queued, whatever the policy, so that it runs after it.
+    pub(crate) fn admit(&mut self, run: Run) -> Admit {
+        let trigger = run.kind == RunKind::Trigger;
+        let limit = if trigger { 1 } else { self.overlap.limit() };
+        if self.running < limit {
+            self.running += 1;
+            return Admit::Start;
+        }
+
+        match self.queued {
+            None if trigger || self.overlap == Overlap::Queue => {
+                self.queued = Some(run);
+                Admit::Queue
+            }
+            Some(_) if trigger => Admit::Merge,
+            _ => {
+                self.skipped += 1;
+                Admit::Skip
+            }
+        }
+    }
+
+    /// Called when an execution is done. Returns the queued run, which takes over its slot.
+    pub(crate) fn finish(&mut self) -> Option<Run> {
+        let queued = self.queued.take();
+        if queued.is_none() {
+            self.running -= 1;
+        }
+        queued
+    }
+}
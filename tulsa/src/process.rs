@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Read,
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, sleep, JoinHandle as ThreadJoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::Command as TokioCommand,
+    task::JoinHandle as TaskJoinHandle,
+};
+
+use crate::history::Execution;
+
+const DEFAULT_OUTPUT_LIMIT: usize = 64 * 1024;
+
+/// How often a synchronous process is checked for exit, timeout or a stop request.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long to keep reading output after a process has exited.
+const READER_GRACE_PERIOD: Duration = Duration::from_millis(100);
+
+/// An external command which can be scheduled like any other task body. An execution succeeds
+/// when the command exits with a zero status.
+#[derive(Clone, Debug)]
+pub struct ProcessTask {
+    program: String,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<PathBuf>,
+    output_limit: usize,
+    timeout: Option<Duration>,
+}
+
+/// What a process wrote before it exited, truncated to the output limit of its `ProcessTask`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessOutput {
+    /// The exit code, which is `None` if the process was killed by a signal.
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+}
+
+impl ProcessTask {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            current_dir: None,
+            output_limit: DEFAULT_OUTPUT_LIMIT,
+            timeout: None,
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args(mut self, args: &[&str]) -> Self {
+        self.args.extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.envs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// The number of bytes to keep from each of stdout and stderr. Anything beyond this is read
+    /// and discarded so that the process never blocks on a full pipe.
+    pub fn output_limit(mut self, bytes: usize) -> Self {
+        self.output_limit = bytes;
+        self
+    }
+
+    /// Kill the process if it is still running after `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .envs(self.envs.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    /// Run the process on the current thread. `stopping` is polled while the process runs, and the
+    /// process is killed as soon as it returns `true`.
+    pub(crate) fn run<F>(&self, stopping: F) -> Execution
+    where
+        F: Fn() -> bool,
+    {
+        let started_at = SystemTime::now();
+        let start = Instant::now();
+
+        let mut child = match self.command().spawn() {
+            Ok(child) => child,
+            Err(e) => return self.spawn_failed(started_at, start, e),
+        };
+        let (stdout, stdout_reader) = capture(child.stdout.take(), self.output_limit);
+        let (stderr, stderr_reader) = capture(child.stderr.take(), self.output_limit);
+
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) if stopping() => {
+                    break kill(
+                        &mut child,
+                        "killed because the task was stopped".to_string(),
+                    )
+                }
+                Ok(None) if self.timed_out(start) => break kill(&mut child, self.timeout_error()),
+                Ok(None) => sleep(POLL_INTERVAL),
+                Err(e) => break Err(e.to_string()),
+            }
+        };
+
+        wait_for_readers([stdout_reader, stderr_reader]);
+        self.finish(started_at, start, status, &stdout, &stderr)
+    }
+
+    /// Run the process on the current runtime. The process is killed if this future is dropped,
+    /// which is how a stopped async task kills its process.
+    pub(crate) async fn run_async(&self) -> Execution {
+        let started_at = SystemTime::now();
+        let start = Instant::now();
+
+        let mut command = TokioCommand::from(self.command());
+        let mut child = match command.kill_on_drop(true).spawn() {
+            Ok(child) => child,
+            Err(e) => return self.spawn_failed(started_at, start, e),
+        };
+        let (stdout, stdout_reader) = capture_async(child.stdout.take(), self.output_limit);
+        let (stderr, stderr_reader) = capture_async(child.stderr.take(), self.output_limit);
+
+        let status = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, child.wait()).await {
+                Ok(status) => status.map_err(|e| e.to_string()),
+                Err(_) => {
+                    let _ = child.kill().await;
+                    Err(self.timeout_error())
+                }
+            },
+            None => child.wait().await.map_err(|e| e.to_string()),
+        };
+
+        wait_for_readers_async([stdout_reader, stderr_reader]).await;
+        self.finish(started_at, start, status, &stdout, &stderr)
+    }
+
+    fn timed_out(&self, start: Instant) -> bool {
+        self.timeout
+            .is_some_and(|timeout| start.elapsed() >= timeout)
+    }
+
+    fn timeout_error(&self) -> String {
+        format!("timed out after {:?}", self.timeout.unwrap_or_default())
+    }
+
+    fn spawn_failed(&self, started_at: SystemTime, start: Instant, e: std::io::Error) -> Execution {
+        let error = format!("failed to spawn {}: {}", self.program, e);
+        Execution::new(started_at, start.elapsed(), Err(error))
+    }
+
+    fn finish(
+        &self,
+        started_at: SystemTime,
+        start: Instant,
+        status: Result<ExitStatus, String>,
+        stdout: &Captured,
+        stderr: &Captured,
+    ) -> Execution {
+        let code = status.as_ref().ok().and_then(|status| status.code());
+        let result = match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("{} exited with {}", self.program, status)),
+            Err(e) => Err(e),
+        };
+
+        let mut execution = Execution::new(started_at, start.elapsed(), result);
+        execution.output = Some(to_output(code, stdout, stderr));
+        execution
+    }
+}
+
+fn kill(child: &mut Child, error: String) -> Result<ExitStatus, String> {
+    let _ = child.kill();
+    let _ = child.wait();
+    Err(error)
+}
+
+/// The bytes read from a pipe, which is shared with the reader so that the output can be taken
+/// even if the reader never finishes, e.g. when a child of the process keeps the pipe open.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<(Vec<u8>, bool)>>);
+
+impl Captured {
+    /// Keep `bytes` up to `limit`, remembering whether anything was dropped.
+    fn push(&self, bytes: &[u8], limit: usize) {
+        let (kept, truncated) = &mut *self.0.lock().unwrap();
+        let room = limit.saturating_sub(kept.len());
+        kept.extend_from_slice(&bytes[..bytes.len().min(room)]);
+        *truncated |= bytes.len() > room;
+    }
+
+    fn take(&self) -> (String, bool) {
+        let (kept, truncated) = &*self.0.lock().unwrap();
+        (String::from_utf8_lossy(kept).into_owned(), *truncated)
+    }
+}
+
+fn to_output(code: Option<i32>, stdout: &Captured, stderr: &Captured) -> ProcessOutput {
+    let (stdout, stdout_truncated) = stdout.take();
+    let (stderr, stderr_truncated) = stderr.take();
+    ProcessOutput {
+        code,
+        stdout,
+        stderr,
+        truncated: stdout_truncated || stderr_truncated,
+    }
+}
+
+fn capture<R>(reader: Option<R>, limit: usize) -> (Captured, Option<ThreadJoinHandle<()>>)
+where
+    R: Read + Send + 'static,
+{
+    let captured = Captured::default();
+    let handle = reader.map(|mut reader| {
+        let captured = captured.clone();
+        thread::spawn(move || {
+            let mut buffer = [0; 8192];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => captured.push(&buffer[..n], limit),
+                }
+            }
+        })
+    });
+    (captured, handle)
+}
+
+fn capture_async<R>(reader: Option<R>, limit: usize) -> (Captured, Option<TaskJoinHandle<()>>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let captured = Captured::default();
+    let handle = reader.map(|mut reader| {
+        let captured = captured.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0; 8192];
+            loop {
+                match reader.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => captured.push(&buffer[..n], limit),
+                }
+            }
+        })
+    });
+    (captured, handle)
+}
+
+/// Give the readers of a finished process a moment to drain its pipes.
+fn wait_for_readers(handles: [Option<ThreadJoinHandle<()>>; 2]) {
+    let deadline = Instant::now() + READER_GRACE_PERIOD;
+    for handle in handles.into_iter().flatten() {
+        while !handle.is_finished() && Instant::now() < deadline {
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+async fn wait_for_readers_async(handles: [Option<TaskJoinHandle<()>>; 2]) {
+    let deadline = tokio::time::Instant::now() + READER_GRACE_PERIOD;
+    for handle in handles.into_iter().flatten() {
+        let abort = handle.abort_handle();
+        if tokio::time::timeout_at(deadline, handle).await.is_err() {
+            abort.abort();
+        }
+    }
+}
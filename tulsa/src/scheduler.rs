@@ -1,39 +1,299 @@
+use chrono_tz::Tz;
 use std::{
-    sync::{mpsc::Receiver, Arc, Mutex},
-    thread::Builder as ThreadBuilder,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{sleep, Builder as ThreadBuilder},
+    time::Duration,
 };
+use tracing::{error, info, warn};
 
 use crate::{
+    admission::{Admission, AdmissionError, Limits},
     async_scheduler::AsyncScheduler,
-    model::{AsyncTask, SyncTask},
+    control,
+    handle::SchedulerHandle,
+    history::DEFAULT_HISTORY_CAPACITY,
+    lease::Lease,
+    metrics::{Metrics, NoopMetrics},
+    model::{AsyncTask, SyncTask, Task},
+    snapshot::Snapshot,
     thread_scheduler::ThreadScheduler,
 };
 
+/// How long a stopped task has to wrap up, unless the scheduler is given a grace period.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
 pub struct Scheduler<T> {
     receiver: Arc<Mutex<Receiver<T>>>,
+    history_capacity: usize,
+    control_socket: Option<PathBuf>,
+    shards: usize,
+    zone: Tz,
+    grace_period: Duration,
+    lease: Option<Box<dyn Lease>>,
+    metrics: Arc<dyn Metrics>,
+    snapshot: Option<Snapshot>,
+    limits: Option<Limits>,
 }
 
 impl<T> Scheduler<T> {
     pub fn new(receiver: Receiver<T>) -> Self {
         let receiver = Arc::new(Mutex::new(receiver));
-        Self { receiver }
+        Self {
+            receiver,
+            history_capacity: DEFAULT_HISTORY_CAPACITY,
+            control_socket: None,
+            shards: 1,
+            zone: Tz::UTC,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            lease: None,
+            metrics: Arc::new(NoopMetrics),
+            snapshot: None,
+            limits: None,
+        }
+    }
+
+    /// The number of executions to keep for each task. A capacity of zero disables the history.
+    pub fn history_capacity(mut self, capacity: usize) -> Self {
+        self.history_capacity = capacity;
+        self
+    }
+
+    /// Listen for `tulsactl` commands on a Unix socket at `path`, which only its owner can use. A
+    /// socket left at `path` is replaced, while any other file is kept and the socket not bound.
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// Split the tasks across `shards` independent command loops, so that a slow command only
+    /// holds up the tasks of its own shard. A task belongs to shard `id % shards`.
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = shards.max(1);
+        self
+    }
+
+    /// The zone of every `ServiceWindow` which does not have one of its own. The default is UTC.
+    pub fn time_zone(mut self, zone: Tz) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    /// How long a stopped task has to return once its `TaskContext` is cancelled. An async task
+    /// is then aborted, while a thread task is left to finish on its own.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Only run the scheduled executions while this instance holds `lease`, so that several
+    /// instances can share the same tasks. A one-shot task waits for the lease, while a trigger
+    /// still runs on any instance.
+    pub fn lease(mut self, lease: impl Lease + 'static) -> Self {
+        self.lease = Some(Box::new(lease));
+        self
+    }
+
+    /// Report registrations, executions and skipped ticks to `metrics`. Give the same metrics to
+    /// the `TaskSender` to also measure the depth of the command queue, unless the scheduler has
+    /// `limits`, in which case it measures the queues of its shards itself.
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Reject the commands over `limits` before they reach the tasks, whichever sender they came
+    /// from. A rejected command is logged and dropped, since only a `TaskSender` can return the
+    /// error to its caller. The `queue_depth` applies to the queue of each shard.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Resume the tasks of `snapshot`, which was taken from another scheduler. Each task still
+    /// has to be sent, after which it keeps the next tick, pause state and counters it had, and
+    /// the frequency an adaptive task changed to.
+    pub fn restore(mut self, snapshot: Snapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// The handle which every shard shares, which gives a single view across all of them.
+    fn handle(&mut self) -> SchedulerHandle {
+        let handle = SchedulerHandle::new(self.history_capacity, self.metrics.clone());
+        if let Some(snapshot) = self.snapshot.take() {
+            handle.restore(snapshot);
+        }
+        self.serve_control_socket(&handle);
+        self.hold_lease(&handle);
+        handle
+    }
+
+    fn serve_control_socket(&self, handle: &SchedulerHandle) {
+        if let Some(path) = &self.control_socket {
+            if let Err(e) = control::serve(path, handle.clone()) {
+                error!("Failed to bind control socket {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn hold_lease(&mut self, handle: &SchedulerHandle) {
+        if let Some(lease) = self.lease.take() {
+            // Nothing runs until the first attempt to take the lease.
+            handle.set_leader(false);
+            renew_lease(lease, handle.clone());
+        }
     }
 }
 
-impl Scheduler<AsyncTask> {
-    pub fn run(self) {
+/// Renew `lease` on a background thread until the scheduler shuts down, and release it then.
+fn renew_lease(mut lease: Box<dyn Lease>, handle: SchedulerHandle) {
+    ThreadBuilder::new()
+        .name("lease".to_string())
+        .spawn(move || {
+            while !handle.is_shut_down() {
+                let leader = lease.acquire().unwrap_or_else(|e| {
+                    warn!("Failed to renew lease: {}", e);
+                    false
+                });
+                if leader != handle.is_leader() {
+                    info!("Lease {}", if leader { "acquired" } else { "lost" });
+                    handle.set_leader(leader);
+                }
+                sleep(lease.renew_interval());
+            }
+
+            if handle.is_leader() {
+                if let Err(e) = lease.release() {
+                    warn!("Failed to release lease: {}", e);
+                }
+            }
+        })
+        .expect("Failed to spawn lease thread.");
+}
+
+impl<T> Scheduler<T>
+where
+    T: Task + Send + 'static,
+{
+    /// One receiver for each shard. With more than one shard or with limits, a router thread
+    /// admits each command and forwards it to the shard which owns its task. With limits, each
+    /// shard queues at most `queue_depth` commands, and the router counts them in the metrics.
+    fn shard_receivers(&self) -> Vec<Arc<Mutex<Receiver<T>>>> {
+        if self.shards == 1 && self.limits.is_none() {
+            return vec![self.receiver.clone()];
+        }
+
+        let queue_depth = self.limits.as_ref().map(|limits| limits.queue_depth);
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..self.shards)
+            .map(|_| {
+                let (sender, receiver) = match queue_depth {
+                    Some(depth) => {
+                        let (sender, receiver) = mpsc::sync_channel(depth);
+                        (ShardSender::Bounded(sender), receiver)
+                    }
+                    None => {
+                        let (sender, receiver) = mpsc::channel();
+                        (ShardSender::Unbounded(sender), receiver)
+                    }
+                };
+                (sender, Arc::new(Mutex::new(receiver)))
+            })
+            .unzip();
+
+        // Once every sender is gone, dropping the shard senders lets each shard know.
+        let receiver = self.receiver.clone();
+        let mut admission = self.limits.clone().map(Admission::new);
+        let metrics = self.metrics.clone();
         ThreadBuilder::new()
-            .name("scheduler".to_string())
-            .spawn(|| AsyncScheduler::new().listen(self.receiver))
-            .expect("Failed to spawn scheduler thread.");
+            .name("scheduler-router".to_string())
+            .spawn(move || loop {
+                let mut task = match receiver.lock().unwrap().recv() {
+                    Ok(task) => task,
+                    Err(_) => break,
+                };
+                let (id, op) = (task.id(), task.op());
+                let Some(admission) = admission.as_mut() else {
+                    let _ = senders[id % senders.len()].send(task);
+                    continue;
+                };
+
+                if let Err(e) = admission.check(&task) {
+                    warn!("Rejected command for task {}: {}", id, e);
+                    continue;
+                }
+                let ticket = Admission::ticket(&mut task);
+                match senders[id % senders.len()].send(task) {
+                    Err(TrySendError::Full(_)) => {
+                        let e = AdmissionError::QueueFull(queue_depth.unwrap_or_default());
+                        warn!("Rejected command for task {}: {}", id, e);
+                    }
+                    Err(TrySendError::Disconnected(_)) => {}
+                    Ok(()) => {
+                        metrics.command_queued();
+                        admission.admit(id, op, ticket);
+                    }
+                }
+            })
+            .expect("Failed to spawn scheduler router thread.");
+
+        receivers
+    }
+}
+
+/// Forwards the commands of the router to a shard, over a bounded channel if the scheduler has
+/// limits.
+enum ShardSender<T> {
+    Bounded(SyncSender<T>),
+    Unbounded(Sender<T>),
+}
+
+impl<T> ShardSender<T> {
+    fn send(&self, task: T) -> Result<(), TrySendError<T>> {
+        match self {
+            ShardSender::Bounded(sender) => sender.try_send(task),
+            ShardSender::Unbounded(sender) => sender
+                .send(task)
+                .map_err(|e| TrySendError::Disconnected(e.0)),
+        }
+    }
+}
+
+impl Scheduler<AsyncTask> {
+    pub fn run(mut self) -> SchedulerHandle {
+        let handle = self.handle();
+        for (idx, receiver) in self.shard_receivers().into_iter().enumerate() {
+            let scheduler_handle = handle.clone();
+            let (zone, grace_period) = (self.zone, self.grace_period);
+            ThreadBuilder::new()
+                .name(format!("scheduler-{}", idx))
+                .spawn(move || {
+                    AsyncScheduler::new(scheduler_handle, zone, grace_period).listen(receiver)
+                })
+                .expect("Failed to spawn scheduler thread.");
+        }
+
+        handle
     }
 }
 
 impl Scheduler<SyncTask> {
-    pub fn run(self) {
-        ThreadBuilder::new()
-            .name("scheduler".to_string())
-            .spawn(|| ThreadScheduler::new().listen(self.receiver))
-            .expect("Failed to spawn scheduler thread.");
+    pub fn run(mut self) -> SchedulerHandle {
+        let handle = self.handle();
+        for (idx, receiver) in self.shard_receivers().into_iter().enumerate() {
+            let scheduler_handle = handle.clone();
+            let (zone, grace_period) = (self.zone, self.grace_period);
+            ThreadBuilder::new()
+                .name(format!("scheduler-{}", idx))
+                .spawn(move || {
+                    ThreadScheduler::new(scheduler_handle, zone, grace_period).listen(receiver)
+                })
+                .expect("Failed to spawn scheduler thread.");
+        }
+
+        handle
     }
 }
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The schedule of every task of a scheduler at one moment, which another scheduler can resume
+/// from. The bodies of the tasks cannot be exported, so each task is rebuilt from its `spec` and
+/// sent to the new scheduler, which picks up its timing from the snapshot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub taken_at: SystemTime,
+    /// Ordered by id.
+    pub tasks: Vec<TaskSnapshot>,
+}
+
+/// The state of a single task in a `Snapshot`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskSnapshot {
+    pub id: usize,
+    /// What the task does, as given to `describe` on the task.
+    pub spec: Option<Value>,
+    /// The current time between two executions, or `None` if the task does not recur.
+    pub frequency: Option<Duration>,
+    /// When the next scheduled execution is due, or `None` if the task does not recur.
+    pub next_tick: Option<SystemTime>,
+    pub paused: bool,
+    /// The number of executions so far.
+    pub runs: u64,
+    /// The number of ticks which were skipped because the task was still running.
+    pub skipped: u64,
+}
+
+/// The wall clock time of `at`.
+pub(crate) fn to_system_time(at: Instant) -> SystemTime {
+    let now = Instant::now();
+    if at >= now {
+        SystemTime::now() + (at - now)
+    } else {
+        SystemTime::now() - (now - at)
+    }
+}
+
+/// The instant of `at`. A time in the past is now, so that it is due right away.
+pub(crate) fn to_instant(at: SystemTime) -> Instant {
+    Instant::now() + at.duration_since(SystemTime::now()).unwrap_or_default()
+}
@@ -1,90 +1,306 @@
+use chrono_tz::Tz;
 use std::{
-    pin::Pin,
+    collections::HashSet,
+    panic::{catch_unwind, AssertUnwindSafe},
     sync::{mpsc::Receiver, Arc, Mutex},
     thread::{sleep, Builder as ThreadBuilder, JoinHandle as ThreadJoinHandle},
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
-use crate::model::{Operation, SyncTask};
+use crate::{
+    admission::Ticket,
+    context::TaskContext,
+    graph::SyncGraph,
+    handle::{SchedulerHandle, TaskControl, Wake},
+    history::{panic_message, Execution, Outcome},
+    model::{Bounds, Operation, SyncBody, SyncFunc, SyncTask, Task},
+    overlap::{Admit, Run, RunKind},
+    snapshot::{to_instant, to_system_time},
+    window::ServiceWindow,
+};
+use tracing::{info, info_span, warn, Span};
+
+/// How often a stopped task is checked until it is done or its grace period is over.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often a once-graph which waits for its service window checks whether it has opened.
+const WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 struct TaskRunner {
     id: usize,
     frequency: Duration,
+    bounds: Option<Bounds>,
+    window: Option<ServiceWindow>,
     thread_handle: Option<ThreadJoinHandle<()>>,
-    runner_data: Arc<Mutex<RunnerData>>,
+    control: Arc<TaskControl>,
+    span: Span,
 }
 
-struct RunnerData {
-    stopping: bool,
+/// What the workers of a task share with its runner.
+struct Work {
+    id: usize,
+    func: SyncFunc,
+    bounds: Option<Bounds>,
+    scheduler: SchedulerHandle,
+    control: Arc<TaskControl>,
+    span: Span,
 }
 
 impl TaskRunner {
-    fn new(id: usize, frequency: Duration) -> Self {
+    fn new(
+        id: usize,
+        frequency: Duration,
+        bounds: Option<Bounds>,
+        window: Option<ServiceWindow>,
+        control: Arc<TaskControl>,
+        span: Span,
+    ) -> Self {
         let thread_handle = None;
-        let runner_data = Arc::new(Mutex::new(RunnerData { stopping: false }));
         Self {
             id,
             frequency,
+            bounds,
+            window,
             thread_handle,
-            runner_data,
+            control,
+            span,
         }
     }
 
-    fn start(&mut self, func: Pin<Box<dyn Fn() + Send + Sync + 'static>>) {
-        println!("Starting {}", self.id);
+    /// Start a thread which waits for each tick, and hands each execution to a worker thread so
+    /// that the overlap policy of the task can be applied to ticks which arrive during a run.
+    fn start(&mut self, func: SyncFunc, ticket: Option<Ticket>, scheduler: SchedulerHandle) {
+        info!(parent: &self.span, "Starting task");
         let frequency = self.frequency;
-        let runner_data = self.runner_data.clone();
+        let window = self.window;
+        let control = self.control.clone();
+        let once = matches!(func, SyncFunc::Graph { once: true, .. });
+        let work = Arc::new(Work {
+            id: self.id,
+            func,
+            bounds: self.bounds,
+            scheduler: scheduler.clone(),
+            control: self.control.clone(),
+            span: self.span.clone(),
+        });
+        let span = self.span.clone();
         let builder = ThreadBuilder::new().name("task".to_string());
 
-        let handle = builder.spawn(move || loop {
-            {
-                if runner_data.lock().unwrap().stopping {
+        let handle = builder.spawn(move || {
+            let _entered = span.enter();
+            // A one-shot task keeps its ticket until it has run, so that it counts against the
+            // limits.
+            let _ticket = ticket;
+            // A task restored from a snapshot keeps the tick it was waiting for.
+            let mut next_tick = control.next_tick().map_or_else(Instant::now, to_instant);
+            let mut workers: Vec<ThreadJoinHandle<()>> = Vec::new();
+
+            loop {
+                control.set_next_tick(to_system_time(next_tick));
+                let run = match control.wait(next_tick) {
+                    Wake::Stop => break,
+                    Wake::Reschedule => {
+                        next_tick = Instant::now() + control.frequency().unwrap_or(frequency);
+                        continue;
+                    }
+                    Wake::Trigger => Run::new(RunKind::Trigger, SystemTime::now()),
+                    Wake::Tick => {
+                        // The tick was due at `next_tick`, which may have been a moment ago.
+                        let scheduled_at = SystemTime::now() - next_tick.elapsed();
+                        next_tick += control.frequency().unwrap_or(frequency);
+                        let blocked = |paused: bool| {
+                            let closed = window.is_some_and(|w| !w.is_open(SystemTime::now()));
+                            paused || closed || !scheduler.is_leader()
+                        };
+                        if !blocked(control.is_paused()) {
+                            Run::new(RunKind::Tick, scheduled_at)
+                        } else if !once {
+                            continue;
+                        } else {
+                            // A once-graph has no next tick, so it waits until it can run.
+                            match control.wait_while(WINDOW_CHECK_INTERVAL, blocked) {
+                                Wake::Stop => break,
+                                Wake::Trigger => Run::new(RunKind::Trigger, SystemTime::now()),
+                                _ => Run::new(RunKind::Tick, scheduled_at),
+                            }
+                        }
+                    }
+                };
+
+                workers.retain(|worker| !worker.is_finished());
+                match control.admit(run) {
+                    Admit::Start => {
+                        let work = work.clone();
+                        let worker = ThreadBuilder::new()
+                            .name("task-worker".to_string())
+                            .spawn(move || work.run(run));
+                        workers.push(worker.unwrap());
+                    }
+                    Admit::Queue => {}
+                    Admit::Skip => scheduler.metrics.tick_skipped(work.id),
+                    Admit::Merge => {
+                        info!(
+                            id = work.id,
+                            "Merged a trigger with the run which is waiting"
+                        );
+                        scheduler.metrics.trigger_merged(work.id);
+                    }
+                }
+
+                if once && run.kind == RunKind::Tick {
                     break;
                 }
             }
 
-            func();
-            sleep(frequency);
+            for worker in workers {
+                let _ = worker.join();
+            }
         });
 
         self.thread_handle = Some(handle.unwrap());
     }
 
     fn stop(&mut self) {
-        println!("Stopping {}", self.id);
-        // Use a block so that the lock is released.
-        {
-            self.runner_data.lock().unwrap().stopping = true;
-        }
+        info!(parent: &self.span, "Stopping task");
+        self.control.stop();
 
         if let Some(handle) = self.thread_handle.take() {
+            // A thread cannot be aborted, so a body which ignores its context is left running.
+            let deadline = Instant::now() + self.control.grace_period();
+            while !handle.is_finished() && Instant::now() < deadline {
+                sleep(STOP_POLL_INTERVAL);
+            }
+            if !handle.is_finished() {
+                warn!(parent: &self.span, "Task did not stop within its grace period");
+                return;
+            }
+
             match handle.join() {
-                Ok(_) => println!("Stopped {}", self.id),
+                Ok(_) => info!(parent: &self.span, "Stopped task"),
                 Err(e) => panic!("{:?}", e),
             }
         }
     }
 }
 
+impl Work {
+    /// Run the task, then any run which was queued behind it.
+    fn run(&self, mut run: Run) {
+        loop {
+            let number = self.control.next_run();
+            let context = TaskContext::new(self.id, number, run.scheduled_at, self.control.clone());
+            let lag = SystemTime::now().duration_since(run.scheduled_at);
+            self.scheduler
+                .metrics
+                .execution_started(self.id, lag.unwrap_or_default());
+            let span = info_span!(parent: &self.span, "execution", run = number, kind = ?run.kind);
+            let next_delay =
+                span.in_scope(|| run_once(&self.func, context, &self.scheduler, &self.control));
+
+            // A triggered execution does not change the schedule.
+            if let (RunKind::Tick, Some(bounds), Some(delay)) = (run.kind, self.bounds, next_delay)
+            {
+                self.control.reschedule(bounds.clamp(delay));
+            }
+
+            match self.control.finish() {
+                Some(queued) if !self.control.is_stopping() => run = queued,
+                _ => break,
+            }
+        }
+    }
+}
+
+/// Run a task once, returning the delay its body suggested for the next execution.
+fn run_once(
+    func: &SyncFunc,
+    context: TaskContext,
+    scheduler: &SchedulerHandle,
+    control: &TaskControl,
+) -> Option<Duration> {
+    let id = context.id();
+    let (execution, next_delay) = match func {
+        SyncFunc::Recurring(body) => execute(body, context),
+        SyncFunc::Graph { graph, .. } => {
+            execute_graph(graph, scheduler, &context);
+            return None;
+        }
+        SyncFunc::Process(process) => (process.run(|| control.is_stopping()), None),
+    };
+    scheduler.record(id, execution);
+    next_delay
+}
+
+/// Run a single execution of a task body, catching a panic so that the task keeps its schedule.
+fn execute(body: &SyncBody, context: TaskContext) -> (Execution, Option<Duration>) {
+    let started_at = SystemTime::now();
+    let start = Instant::now();
+
+    match catch_unwind(AssertUnwindSafe(|| body(context))) {
+        Ok(report) => {
+            let execution = Execution::new(started_at, start.elapsed(), report.result);
+            (execution, report.next_delay)
+        }
+        Err(payload) => {
+            let execution =
+                Execution::panicked(started_at, start.elapsed(), panic_message(payload));
+            (execution, None)
+        }
+    }
+}
+
+/// Run each node of a graph in topological order, skipping the nodes with a dependency which did
+/// not succeed during this run.
+fn execute_graph(graph: &SyncGraph, scheduler: &SchedulerHandle, context: &TaskContext) {
+    let mut failed = HashSet::new();
+
+    for idx in graph.order.iter() {
+        let node = &graph.nodes[*idx];
+        let execution = match graph.failed_dependency(node.id, &failed) {
+            Some(dependency) => Execution::skipped(dependency),
+            None => execute(&node.body, context.clone()).0,
+        };
+
+        if execution.outcome != Outcome::Success {
+            failed.insert(node.id);
+        }
+        scheduler.record(node.id, execution);
+    }
+}
+
 pub(crate) struct ThreadScheduler {
     tasks: Arc<Mutex<Vec<TaskRunner>>>,
+    handle: SchedulerHandle,
+    /// The zone of a service window which does not have one of its own.
+    zone: Tz,
+    grace_period: Duration,
 }
 
 impl ThreadScheduler {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(handle: SchedulerHandle, zone: Tz, grace_period: Duration) -> Self {
         ThreadScheduler {
             tasks: Arc::new(Mutex::new(Vec::<TaskRunner>::new())),
+            handle,
+            zone,
+            grace_period,
         }
     }
 
     pub(crate) fn listen(&mut self, receiver: Arc<Mutex<Receiver<SyncTask>>>) {
-        println!("ThreadScheduler initialized.");
+        info!("ThreadScheduler initialized.");
 
         let r = receiver.clone();
+        // The task threads keep running once every sender is gone.
         loop {
             match r.lock().unwrap().recv() {
-                Ok(task) => self.handle(task),
-                Err(e) => eprintln!("{}", e),
+                Ok(task) => {
+                    self.handle.metrics.command_received();
+                    self.handle(task);
+                }
+                Err(e) => {
+                    info!("Stopped listening: {}", e);
+                    break;
+                }
             }
         }
     }
@@ -98,8 +314,29 @@ impl ThreadScheduler {
     }
 
     fn start(&mut self, task: SyncTask) {
-        let mut runner = TaskRunner::new(task.id, task.frequency);
-        runner.start(task.func);
+        if let SyncFunc::Graph { ref graph, .. } = task.func {
+            let node_ids = graph.node_ids();
+            self.handle
+                .history
+                .lock()
+                .unwrap()
+                .add_nodes(task.id, node_ids);
+        }
+
+        let control = Arc::new(TaskControl::new(
+            task.frequency(),
+            task.bounds,
+            task.overlap,
+            self.grace_period,
+            task.spec.clone(),
+        ));
+        self.handle.register(task.id, control.clone());
+
+        let window = task.window.map(|window| window.or_zone(self.zone));
+        let span = task.span();
+        let mut runner =
+            TaskRunner::new(task.id, task.frequency, task.bounds, window, control, span);
+        runner.start(task.func, task.ticket, self.handle.clone());
         self.tasks.lock().unwrap().push(runner);
     }
 
@@ -109,16 +346,37 @@ impl ThreadScheduler {
             runners[idx].stop();
             runners.remove(idx);
         }
+        self.handle.unregister(task_id);
     }
 
     fn handle(&mut self, task: SyncTask) {
+        if self.handle.is_shut_down() && task.op != Operation::Delete {
+            warn!(id = task.id, "Ignoring task after shutdown");
+            return;
+        }
+        // A recurring task without a frequency would run without a pause, so it is left out and
+        // the task it updates keeps running.
+        let registers = matches!(task.op, Operation::Create | Operation::Update);
+        if registers && task.frequency() == Some(Duration::ZERO) {
+            warn!(id = task.id, "Ignoring recurring task without a frequency");
+            return;
+        }
+
         match task.op {
             Operation::Create => self.start(task),
-            Operation::Delete => self.stop(task.id),
+            Operation::Delete => {
+                self.stop(task.id);
+                self.handle.history.lock().unwrap().remove(task.id);
+            }
             Operation::Update => {
                 self.stop(task.id);
                 self.start(task);
             }
+            Operation::Trigger => {
+                if !self.handle.trigger(task.id) {
+                    warn!(id = task.id, "Cannot trigger unknown task");
+                }
+            }
         }
     }
 }
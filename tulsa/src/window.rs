@@ -0,0 +1,120 @@
+use chrono::{DateTime, Days, NaiveDate, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::{fmt, time::SystemTime};
+
+/// The latest hour a service day time can have, so that a window can run well past midnight.
+const MAX_HOURS: i64 = 47;
+
+/// Used to indicate a `ServiceWindow` could not be parsed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WindowError {
+    /// A time is not `HH:MM` or `HH:MM:SS`, or is past `47:59:59`.
+    InvalidTime(String),
+    /// The window from the first time to the second is empty or longer than a day, even with
+    /// the end on the next day.
+    InvalidRange(String, String),
+}
+
+impl fmt::Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowError::InvalidTime(time) => write!(f, "invalid service day time {:?}", time),
+            WindowError::InvalidRange(start, end) => write!(
+                f,
+                "service window from {:?} to {:?} is empty or longer than a day",
+                start, end
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WindowError {}
+
+/// The part of each service day during which the ticks of a recurring task are run. Ticks outside
+/// of the window are dropped, while a trigger still runs the task right away.
+///
+/// Times are measured from "noon minus 12h" in local time, as in GTFS, so they can go past
+/// `24:00` for service which runs after midnight. On the day of a DST transition this keeps
+/// times after the transition on the wall clock, while times before it shift by an hour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServiceWindow {
+    start: TimeDelta,
+    end: TimeDelta,
+    zone: Option<Tz>,
+}
+
+impl ServiceWindow {
+    /// A window from `start` to `end`, each given as `HH:MM` or `HH:MM:SS`. An `end` which is
+    /// before `start` falls on the next day, so "05:00" to "01:30" is the same as "05:00" to
+    /// "25:30". The window must not be empty or longer than a day.
+    pub fn new(start: &str, end: &str) -> Result<Self, WindowError> {
+        let invalid = || WindowError::InvalidRange(start.to_string(), end.to_string());
+        let (start, mut end) = (parse_time(start)?, parse_time(end)?);
+        if end < start {
+            end += TimeDelta::days(1);
+        }
+        if end <= start || end - start > TimeDelta::days(1) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            start,
+            end,
+            zone: None,
+        })
+    }
+
+    /// The zone of the window. Without one, the window uses the zone of its scheduler.
+    pub fn zone(mut self, zone: Tz) -> Self {
+        self.zone = Some(zone);
+        self
+    }
+
+    /// Returns `true` if `at` falls inside the window on any service day.
+    pub fn is_open(&self, at: SystemTime) -> bool {
+        let at = DateTime::<Utc>::from(at);
+        let zone = self.zone.unwrap_or(Tz::UTC);
+        let today = at.with_timezone(&zone).date_naive();
+
+        // A window which runs past midnight can still be open from one of the days before.
+        (0..=2)
+            .filter_map(|days| today.checked_sub_days(Days::new(days)))
+            .filter_map(|day| service_day_start(zone, day))
+            .any(|start| start + self.start <= at && at < start + self.end)
+    }
+
+    /// Use `zone` unless the window has a zone of its own.
+    pub(crate) fn or_zone(mut self, zone: Tz) -> Self {
+        self.zone.get_or_insert(zone);
+        self
+    }
+}
+
+/// Noon minus 12h on `day`, which is midnight except on the day of a DST transition.
+fn service_day_start(zone: Tz, day: NaiveDate) -> Option<DateTime<Utc>> {
+    let noon = zone
+        .from_local_datetime(&day.and_hms_opt(12, 0, 0)?)
+        .earliest()?;
+    Some(noon.with_timezone(&Utc) - TimeDelta::hours(12))
+}
+
+fn parse_time(time: &str) -> Result<TimeDelta, WindowError> {
+    let invalid = || WindowError::InvalidTime(time.to_string());
+
+    let parts = time
+        .split(':')
+        .map(|part| part.parse::<i64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes] => (hours, minutes, 0),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return Err(invalid()),
+    };
+
+    if !(0..=MAX_HOURS).contains(&hours)
+        || !(0..60).contains(&minutes)
+        || !(0..60).contains(&seconds)
+    {
+        return Err(invalid());
+    }
+    Ok(TimeDelta::seconds(hours * 3600 + minutes * 60 + seconds))
+}
@@ -1,15 +1,48 @@
 #[cfg(test)]
 mod tests {
     use std::{
-        fs::{File, OpenOptions},
-        io::prelude::*,
+        fs::{self, File, OpenOptions},
+        io::{prelude::*, BufReader},
+        os::unix::{fs::PermissionsExt, net::UnixStream},
+        path::Path,
         process::Command,
-        sync::{mpsc, Mutex},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc, Arc, Mutex,
+        },
         thread,
-        time::Duration,
+        time::{Duration, Instant, SystemTime},
     };
 
-    use tulsa::{AsyncTask, Scheduler, SyncTask};
+    use chrono::{TimeZone, Timelike, Utc};
+    use tracing::{
+        field::{Field, Visit},
+        info_span,
+        span::{Attributes, Id},
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    use tulsa::{
+        channel,
+        control::{Request, Response},
+        AdmissionError, AsyncGraph, AsyncTask, Bounds, BoundsError, FileLease, GraphError,
+        InMemoryMetrics, Lease, Limits, Outcome, Overlap, ProcessTask, Scheduler, ServiceWindow,
+        Snapshot, SyncGraph, SyncTask, TaskContext, TaskInfo, TaskSnapshot, Tz, WindowError,
+    };
+
+    /// Poll `condition` until it holds, for at most 5 seconds, so that a test does not depend on
+    /// how loaded the machine is.
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
 
     fn wc(file_path: &str) -> i32 {
         let output = Command::new("wc")
@@ -69,6 +102,10 @@ mod tests {
         })
     }
 
+    fn boom() {
+        panic!("boom");
+    }
+
     #[test]
     fn async_scheduler_create() {
         let (sender, receiver) = mpsc::channel();
@@ -202,4 +239,1325 @@ mod tests {
         thread::sleep(Duration::from_millis(550));
         assert_eq!(wc(FILE_NAME), 6);
     }
+
+    #[test]
+    fn async_scheduler_history() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver)
+            .history_capacity(3)
+            .run();
+
+        let task_id: usize = 3;
+        let task = AsyncTask::recurring(task_id, Duration::from_millis(100), || async { boom() });
+        sender.send(task).unwrap();
+
+        // Six executions will have run, but only the last three are kept
+        thread::sleep(Duration::from_millis(550));
+        let history = handle.history(task_id);
+        assert_eq!(history.len(), 3);
+        assert!(history.iter().all(|e| e.outcome == Outcome::Panic));
+        assert_eq!(history[0].error.as_deref(), Some("boom"));
+        assert!(history[0].started_at < history[2].started_at);
+
+        sender.send(AsyncTask::stop(task_id)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(handle.history(task_id).is_empty());
+    }
+
+    #[test]
+    fn sync_scheduler_history() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver)
+            .history_capacity(3)
+            .run();
+
+        let task_id: usize = 3;
+        let task = SyncTask::new(task_id, Duration::from_millis(100), || {
+            Err::<(), _>("bad key")
+        });
+        sender.send(task).unwrap();
+
+        // Six executions will have run, but only the last three are kept
+        thread::sleep(Duration::from_millis(550));
+        let history = handle.history(task_id);
+        assert_eq!(history.len(), 3);
+        assert!(history.iter().all(|e| e.outcome == Outcome::Failure));
+        assert_eq!(history[0].error.as_deref(), Some("bad key"));
+        assert!(history[0].started_at < history[2].started_at);
+
+        sender.send(SyncTask::stop(task_id)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(handle.history(task_id).is_empty());
+    }
+
+    #[test]
+    fn graph_validation() {
+        let graph = SyncGraph::new()
+            .task(1, || {})
+            .task(2, || {})
+            .task(3, || {})
+            .edge(1, 2)
+            .edge(2, 3)
+            .edge(3, 2);
+        let error = SyncTask::graph_once(4, graph).err();
+        assert_eq!(error, Some(GraphError::Cycle(vec![2, 3])));
+
+        let graph = AsyncGraph::new().task(1, || async {}).edge(1, 2);
+        let error = AsyncTask::graph_once(4, graph).err();
+        assert_eq!(error, Some(GraphError::UnknownNode(2)));
+
+        let graph = SyncGraph::new().task(1, || {}).task(1, || {});
+        let error = SyncTask::graph(4, Duration::from_millis(100), graph).err();
+        assert_eq!(error, Some(GraphError::DuplicateNode(1)));
+    }
+
+    #[test]
+    fn async_scheduler_graph_once() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let push = |id: usize| {
+            let order = order.clone();
+            move || {
+                let order = order.clone();
+                async move { order.lock().unwrap().push(id) }
+            }
+        };
+
+        // Nodes are added in the reverse order of their dependencies
+        let graph = AsyncGraph::new()
+            .task(13, push(13))
+            .task(12, push(12))
+            .task(11, push(11))
+            .edge(11, 12)
+            .edge(12, 13);
+        sender
+            .send(AsyncTask::graph_once(10, graph).unwrap())
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(*order.lock().unwrap(), vec![11, 12, 13]);
+        assert_eq!(handle.history(13).len(), 1);
+    }
+
+    #[test]
+    fn sync_scheduler_graph() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).run();
+
+        // 12 fails, so 13 is skipped while 14 still runs after 11
+        let graph = SyncGraph::new()
+            .task(11, || {})
+            .task(12, || Err::<(), _>("download failed"))
+            .task(13, || {})
+            .task(14, || {})
+            .edge(11, 12)
+            .edge(12, 13)
+            .edge(11, 14);
+        let task = SyncTask::graph(10, Duration::from_millis(100), graph).unwrap();
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(250));
+        let outcomes =
+            |id: usize| -> Vec<Outcome> { handle.history(id).iter().map(|e| e.outcome).collect() };
+        assert_eq!(outcomes(11), vec![Outcome::Success; 3]);
+        assert_eq!(outcomes(12), vec![Outcome::Failure; 3]);
+        assert_eq!(outcomes(13), vec![Outcome::Skipped; 3]);
+        assert_eq!(outcomes(14), vec![Outcome::Success; 3]);
+        assert_eq!(
+            handle.history(13)[0].error.as_deref(),
+            Some("dependency 12 did not succeed")
+        );
+
+        sender.send(SyncTask::stop(10)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(handle.history(11).is_empty());
+    }
+
+    #[test]
+    fn admission() {
+        let limits = Limits {
+            max_tasks: 2,
+            min_frequency: Duration::from_millis(100),
+            queue_depth: 3,
+        };
+        let (sender, _receiver) = channel::<SyncTask>(limits);
+        let task = |id: usize, millis: u64| SyncTask::new(id, Duration::from_millis(millis), || {});
+
+        assert_eq!(
+            sender.send(task(1, 50)),
+            Err(AdmissionError::FrequencyTooLow(Duration::from_millis(100)))
+        );
+        assert_eq!(sender.send(task(1, 100)), Ok(()));
+        assert_eq!(sender.send(task(2, 100)), Ok(()));
+        assert_eq!(
+            sender.send(task(3, 100)),
+            Err(AdmissionError::TooManyTasks(2))
+        );
+
+        // Updating a registered task does not count against the limit, but fills the queue
+        assert_eq!(
+            sender.send(SyncTask::update(2, Duration::from_secs(1), || {})),
+            Ok(())
+        );
+        assert_eq!(
+            sender.send(SyncTask::stop(1)),
+            Err(AdmissionError::QueueFull(3))
+        );
+    }
+
+    #[test]
+    fn admission_with_scheduler() {
+        let limits = Limits {
+            max_tasks: 1,
+            ..Limits::default()
+        };
+        let (sender, receiver) = channel::<AsyncTask>(limits);
+        Scheduler::<AsyncTask>::new(receiver).run();
+
+        let task = |id: usize| AsyncTask::recurring(id, Duration::from_secs(1), || async {});
+        assert_eq!(sender.send(task(1)), Ok(()));
+        assert_eq!(sender.send(task(2)), Err(AdmissionError::TooManyTasks(1)));
+
+        // Stopping a task frees up its slot
+        assert_eq!(sender.send(AsyncTask::stop(1)), Ok(()));
+        assert_eq!(sender.send(task(2)), Ok(()));
+    }
+
+    #[test]
+    fn admission_in_scheduler() {
+        // A plain channel does not check the limits, so the scheduler does
+        let (sender, receiver) = mpsc::channel();
+        let limits = Limits {
+            max_tasks: 2,
+            min_frequency: Duration::from_millis(100),
+            ..Limits::default()
+        };
+        let handle = Scheduler::<SyncTask>::new(receiver).limits(limits).run();
+        let task = |id: usize, millis: u64| SyncTask::new(id, Duration::from_millis(millis), || {});
+
+        sender.send(task(1, 10)).unwrap();
+        for id in 2..10 {
+            sender.send(task(id, 100)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        let ids: Vec<usize> = handle.tasks().iter().map(|task| task.id).collect();
+        assert_eq!(ids, [2, 3]);
+
+        // Stopping a task frees up its slot
+        sender.send(SyncTask::stop(2)).unwrap();
+        sender.send(task(10, 100)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let ids: Vec<usize> = handle.tasks().iter().map(|task| task.id).collect();
+        assert_eq!(ids, [3, 10]);
+    }
+
+    #[test]
+    fn zero_frequency() {
+        let (sync_sender, receiver) = mpsc::channel();
+        let sync_handle = Scheduler::<SyncTask>::new(receiver).run();
+        let (async_sender, receiver) = mpsc::channel();
+        let async_handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        // A task without a frequency is never registered, and does not replace the one it updates
+        sync_sender
+            .send(SyncTask::new(14, Duration::ZERO, || {}))
+            .unwrap();
+        sync_sender
+            .send(SyncTask::new(15, Duration::from_secs(10), || {}))
+            .unwrap();
+        sync_sender
+            .send(SyncTask::update(15, Duration::ZERO, || {}))
+            .unwrap();
+        let task = |frequency| AsyncTask::recurring(16, frequency, || async {});
+        async_sender.send(task(Duration::ZERO)).unwrap();
+        async_sender.send(task(Duration::from_secs(10))).unwrap();
+        let update = AsyncTask::update_recurring(16, Duration::ZERO, || async {});
+        async_sender.send(update).unwrap();
+
+        assert!(wait_until(|| sync_handle.history(15).len() == 1));
+        assert!(wait_until(|| async_handle.history(16).len() == 1));
+        thread::sleep(Duration::from_millis(100));
+        let tasks = sync_handle.tasks();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].frequency, Some(Duration::from_secs(10)));
+        let tasks = async_handle.tasks();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].frequency, Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn admission_one_shot() {
+        let limits = Limits {
+            max_tasks: 1,
+            ..Limits::default()
+        };
+        let (sender, receiver) = channel::<AsyncTask>(limits);
+        Scheduler::<AsyncTask>::new(receiver).run();
+        let task = |id: usize| AsyncTask::recurring(id, Duration::from_secs(1), || async {});
+
+        // A one-shot task counts against the limit until it has run
+        let release = Arc::new(tokio::sync::Notify::new());
+        let notified = release.clone();
+        let once = AsyncTask::new(1, async move { notified.notified().await });
+        assert_eq!(sender.send(once), Ok(()));
+        assert_eq!(sender.send(task(2)), Err(AdmissionError::TooManyTasks(1)));
+
+        release.notify_one();
+        assert!(wait_until(|| sender.send(task(2)).is_ok()));
+    }
+
+    #[test]
+    fn admission_in_scheduler_queue() {
+        let (sender, receiver) = mpsc::channel();
+        let limits = Limits {
+            queue_depth: 1,
+            ..Limits::default()
+        };
+        let handle = Scheduler::<SyncTask>::new(receiver)
+            .limits(limits)
+            .grace_period(Duration::from_millis(500))
+            .run();
+        let task = |id: usize| SyncTask::new(id, Duration::from_secs(1), || {});
+
+        // The body ignores its context, so stopping the task holds up the shard
+        let sleep = || thread::sleep(Duration::from_secs(1));
+        sender
+            .send(SyncTask::new(1, Duration::from_secs(1), sleep))
+            .unwrap();
+        assert!(wait_until(|| handle.tasks().len() == 1));
+        thread::sleep(Duration::from_millis(50));
+        sender.send(SyncTask::stop(1)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // Only one command fits in the queue of the shard while it is held up
+        for id in 2..5 {
+            sender.send(task(id)).unwrap();
+        }
+        assert!(wait_until(|| handle
+            .tasks()
+            .iter()
+            .any(|task| task.id == 2)));
+        thread::sleep(Duration::from_millis(100));
+        let ids: Vec<usize> = handle.tasks().iter().map(|task| task.id).collect();
+        assert_eq!(ids, [2]);
+    }
+
+    #[test]
+    fn sync_scheduler_process() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).run();
+
+        let process = ProcessTask::new("sh")
+            .args(&["-c", "echo $GREETING; pwd; echo oops >&2; exit 3"])
+            .env("GREETING", "hello")
+            .current_dir("/tmp");
+        sender
+            .send(SyncTask::process(5, Duration::from_secs(10), process))
+            .unwrap();
+
+        let process = ProcessTask::new("sh")
+            .args(&["-c", "yes | head -c 10000"])
+            .output_limit(100);
+        sender
+            .send(SyncTask::process(6, Duration::from_secs(10), process))
+            .unwrap();
+
+        let process = ProcessTask::new("sleep")
+            .arg("5")
+            .timeout(Duration::from_millis(100));
+        sender
+            .send(SyncTask::process(7, Duration::from_secs(10), process))
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(400));
+
+        let execution = &handle.history(5)[0];
+        let output = execution.output.as_ref().unwrap();
+        assert_eq!(execution.outcome, Outcome::Failure);
+        assert_eq!(output.code, Some(3));
+        assert_eq!(output.stdout, "hello\n/tmp\n");
+        assert_eq!(output.stderr, "oops\n");
+
+        let output = handle.history(6)[0].output.clone().unwrap();
+        assert_eq!(output.code, Some(0));
+        assert_eq!(output.stdout.len(), 100);
+        assert!(output.truncated);
+
+        let execution = &handle.history(7)[0];
+        assert_eq!(execution.outcome, Outcome::Failure);
+        assert_eq!(execution.error.as_deref(), Some("timed out after 100ms"));
+        assert!(execution.duration < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn process_killed_on_stop() {
+        static SYNC_FILE_NAME: &str = "/tmp/tulsa_sync_process.txt";
+        static ASYNC_FILE_NAME: &str = "/tmp/tulsa_async_process.txt";
+        let _ = fs::remove_file(SYNC_FILE_NAME);
+        let _ = fs::remove_file(ASYNC_FILE_NAME);
+
+        // Each process would create its file if it was not killed first
+        let process = |file_name: &str| {
+            ProcessTask::new("sh").args(&["-c", &format!("sleep 0.5 && touch {}", file_name)])
+        };
+
+        let (sync_sender, receiver) = mpsc::channel();
+        Scheduler::<SyncTask>::new(receiver).run();
+        let task = SyncTask::process(8, Duration::from_secs(10), process(SYNC_FILE_NAME));
+        sync_sender.send(task).unwrap();
+
+        let (async_sender, receiver) = mpsc::channel();
+        Scheduler::<AsyncTask>::new(receiver).run();
+        let task = AsyncTask::process(8, Duration::from_secs(10), process(ASYNC_FILE_NAME));
+        async_sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+        sync_sender.send(SyncTask::stop(8)).unwrap();
+        async_sender.send(AsyncTask::stop(8)).unwrap();
+
+        thread::sleep(Duration::from_millis(800));
+        assert!(!Path::new(SYNC_FILE_NAME).exists());
+        assert!(!Path::new(ASYNC_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn sync_scheduler_pause_and_trigger() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).run();
+
+        let task_id: usize = 20;
+        sender
+            .send(SyncTask::new(task_id, Duration::from_millis(100), || {}))
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            handle.tasks(),
+            vec![TaskInfo {
+                id: task_id,
+                frequency: Some(Duration::from_millis(100)),
+                paused: false,
+                skipped: 0,
+            }]
+        );
+
+        // Scheduled executions are skipped while paused, but a trigger still runs the task
+        assert!(handle.pause(task_id));
+        assert!(handle.tasks()[0].paused);
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(handle.history(task_id).len(), 1);
+        assert!(handle.trigger(task_id));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(handle.history(task_id).len(), 2);
+
+        assert!(handle.resume(task_id));
+        thread::sleep(Duration::from_millis(200));
+        assert!(handle.history(task_id).len() > 2);
+
+        assert!(!handle.pause(99));
+    }
+
+    #[test]
+    fn async_scheduler_pause_and_trigger() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        let task_id: usize = 21;
+        let task = AsyncTask::recurring(task_id, Duration::from_secs(10), || async {});
+        sender.send(task).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(handle.history(task_id).len(), 1);
+
+        assert!(handle.trigger(task_id));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(handle.history(task_id).len(), 2);
+    }
+
+    #[test]
+    fn trigger_operation() {
+        let limits = Limits {
+            min_frequency: Duration::from_millis(100),
+            ..Limits::default()
+        };
+        let (sender, receiver) = channel::<SyncTask>(limits);
+        let handle = Scheduler::<SyncTask>::new(receiver).run();
+
+        // The trigger arrives while the first execution is running, so it runs right after it
+        let task_id: usize = 25;
+        let running = Arc::new(Mutex::new(0));
+        let overlaps = Arc::new(Mutex::new(0));
+        let (r, o) = (running.clone(), overlaps.clone());
+        let task = SyncTask::new(task_id, Duration::from_secs(10), move || {
+            *r.lock().unwrap() += 1;
+            if *r.lock().unwrap() > 1 {
+                *o.lock().unwrap() += 1;
+            }
+            thread::sleep(Duration::from_millis(100));
+            *r.lock().unwrap() -= 1;
+        });
+        sender.send(task).unwrap();
+        assert!(wait_until(|| *running.lock().unwrap() == 1));
+        assert_eq!(sender.send(SyncTask::trigger(task_id)), Ok(()));
+
+        assert!(wait_until(|| handle.history(task_id).len() == 2));
+        assert_eq!(*overlaps.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn adaptive_frequency() {
+        let (sync_sender, receiver) = mpsc::channel();
+        let sync_handle = Scheduler::<SyncTask>::new(receiver).run();
+        let (async_sender, receiver) = mpsc::channel();
+        let async_handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        // Each body asks for a delay outside of its bounds, so the nearest bound is used
+        let task = SyncTask::new(30, Duration::from_secs(1), || Duration::ZERO)
+            .adaptive(Duration::from_millis(50), Duration::from_secs(1))
+            .unwrap();
+        sync_sender.send(task).unwrap();
+        let task = AsyncTask::recurring(31, Duration::from_secs(1), || async {
+            Ok::<_, String>(Duration::from_secs(60))
+        })
+        .adaptive(Duration::from_millis(100), Duration::from_millis(200))
+        .unwrap();
+        async_sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(450));
+        assert!(sync_handle.history(30).len() >= 8);
+        assert_eq!(
+            sync_handle.tasks()[0].frequency,
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(async_handle.history(31).len(), 3);
+        assert_eq!(
+            async_handle.tasks()[0].frequency,
+            Some(Duration::from_millis(200))
+        );
+
+        // The lower bound must also respect the admission limits
+        let (sender, _receiver) = channel::<SyncTask>(Limits::default());
+        let task = SyncTask::new(32, Duration::from_secs(5), || Duration::ZERO)
+            .adaptive(Duration::from_millis(100), Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            sender.send(task),
+            Err(AdmissionError::FrequencyTooLow(Duration::from_secs(1)))
+        );
+    }
+
+    #[test]
+    fn adaptive_bounds() {
+        let task = || AsyncTask::recurring(33, Duration::from_secs(1), || async { Duration::ZERO });
+        let (min, max) = (Duration::from_secs(2), Duration::from_secs(1));
+        assert_eq!(
+            task().adaptive(Duration::ZERO, max).err(),
+            Some(BoundsError::ZeroMin)
+        );
+        assert_eq!(
+            task().adaptive(min, max).err(),
+            Some(BoundsError::MinAboveMax { min, max })
+        );
+        assert!(task().adaptive(max, min).is_ok());
+    }
+
+    #[test]
+    fn adaptive_zero_delay() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        // Bounds which are set without `adaptive` still never reschedule the task without a delay
+        let mut task =
+            AsyncTask::recurring(34, Duration::from_millis(50), || async { Duration::ZERO });
+        task.bounds = Some(Bounds {
+            min: Duration::ZERO,
+            max: Duration::from_secs(1),
+        });
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(handle.history(34).len() > 2);
+        assert_eq!(handle.tasks()[0].frequency, Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn overlap() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).run();
+
+        // Each execution takes 1.3 ticks
+        let slow = || thread::sleep(Duration::from_millis(130));
+        let task = SyncTask::new(40, Duration::from_millis(100), slow).overlap(Overlap::Skip);
+        sender.send(task).unwrap();
+        let task = SyncTask::new(41, Duration::from_millis(100), slow).overlap(Overlap::Queue);
+        sender.send(task).unwrap();
+
+        // Every other tick is skipped, or the executions run back to back
+        thread::sleep(Duration::from_millis(500));
+        let tasks = handle.tasks();
+        assert_eq!(handle.history(40).len(), 2);
+        assert_eq!(tasks[0].skipped, 2);
+        assert_eq!(handle.history(41).len(), 3);
+        assert_eq!(tasks[1].skipped, 0);
+    }
+
+    #[test]
+    fn concurrent_overlap() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (r, m) = (running.clone(), most.clone());
+        let task = AsyncTask::recurring(42, Duration::from_millis(100), move || {
+            let (r, m) = (r.clone(), m.clone());
+            async move {
+                m.fetch_max(r.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(250)).await;
+                r.fetch_sub(1, Ordering::SeqCst);
+            }
+        })
+        .overlap(Overlap::Concurrent(2));
+        sender.send(task).unwrap();
+
+        // Ticks at 0 and 100 run together, 200 is skipped and 300 takes the first slot back
+        thread::sleep(Duration::from_millis(450));
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(handle.tasks()[0].skipped, 1);
+        assert_eq!(handle.history(42).len(), 2);
+    }
+
+    #[test]
+    fn concurrent_trigger() {
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver)
+            .metrics(metrics.clone())
+            .run();
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let released = Arc::new(AtomicBool::new(false));
+        let (r, m, done) = (running.clone(), most.clone(), released.clone());
+        let task = SyncTask::new(43, Duration::from_secs(10), move || {
+            m.fetch_max(r.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            while !done.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            r.fetch_sub(1, Ordering::SeqCst);
+        })
+        .overlap(Overlap::Concurrent(2));
+        sender.send(task).unwrap();
+        assert!(wait_until(|| running.load(Ordering::SeqCst) == 1));
+
+        // A trigger waits for the execution in progress despite the free slot, and the second
+        // trigger is merged with the first
+        sender.send(SyncTask::trigger(43)).unwrap();
+        sender.send(SyncTask::trigger(43)).unwrap();
+        assert!(wait_until(|| metrics
+            .render()
+            .contains("tulsa_merged_triggers_total{task=\"43\"} 1\n")));
+        released.store(true, Ordering::SeqCst);
+        assert!(wait_until(|| handle.history(43).len() == 2));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(handle.history(43).len(), 2);
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn shards() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).shards(2).run();
+
+        // Stopping task 50 takes a while, which only holds up its own shard
+        let slow = || thread::sleep(Duration::from_millis(400));
+        sender
+            .send(SyncTask::new(50, Duration::from_secs(10), slow))
+            .unwrap();
+        for id in 51..54 {
+            sender
+                .send(SyncTask::new(id, Duration::from_secs(10), || {}))
+                .unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+        let ids: Vec<usize> = handle.tasks().iter().map(|task| task.id).collect();
+        assert_eq!(ids, vec![50, 51, 52, 53]);
+
+        sender.send(SyncTask::stop(50)).unwrap();
+        sender
+            .send(SyncTask::new(55, Duration::from_secs(10), || {}))
+            .unwrap();
+        sender
+            .send(SyncTask::new(54, Duration::from_secs(10), || {}))
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(handle.history(55).len(), 1);
+        assert!(handle.history(54).is_empty());
+
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(handle.history(54).len(), 1);
+    }
+
+    #[test]
+    fn shutdown() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).run();
+
+        sender
+            .send(SyncTask::new(22, Duration::from_millis(100), || {}))
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+        handle.shutdown();
+        assert!(handle.is_shut_down());
+        assert!(handle.tasks().is_empty());
+
+        // Tasks created after a shutdown are ignored
+        sender
+            .send(SyncTask::new(23, Duration::from_millis(100), || {}))
+            .unwrap();
+        thread::sleep(Duration::from_millis(150));
+        assert!(handle.tasks().is_empty());
+        assert!(handle.history(23).is_empty());
+        assert_eq!(handle.history(22).len(), 1);
+    }
+
+    #[test]
+    fn control_socket() {
+        static SOCKET_PATH: &str = "/tmp/tulsa_control_test.sock";
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver)
+            .control_socket(SOCKET_PATH)
+            .run();
+
+        let task = AsyncTask::recurring(24, Duration::from_secs(10), || async {});
+        sender.send(task).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let stream = UnixStream::connect(SOCKET_PATH).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = |request: &str| -> Response {
+            writeln!(&stream, "{}", request).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        };
+
+        match request(r#"{"command": "list"}"#) {
+            Response::Tasks { tasks } => assert_eq!(tasks[0].id, 24),
+            response => panic!("unexpected response {:?}", response),
+        }
+        assert!(matches!(
+            request(r#"{"command": "pause", "id": 24}"#),
+            Response::Ok
+        ));
+        assert!(handle.tasks()[0].paused);
+        assert!(matches!(
+            request(r#"{"command": "resume", "id": 25}"#),
+            Response::Error { .. }
+        ));
+        assert!(matches!(
+            request(r#"{"command": "restart"}"#),
+            Response::Error { .. }
+        ));
+
+        let shutdown = serde_json::to_string(&Request::Shutdown).unwrap();
+        assert!(matches!(request(&shutdown), Response::Ok));
+        assert!(handle.is_shut_down());
+        assert!(!Path::new(SOCKET_PATH).exists());
+    }
+
+    #[test]
+    fn control_socket_closed() {
+        static SOCKET_PATH: &str = "/tmp/tulsa_control_closed_test.sock";
+        let (_sender, receiver) = mpsc::channel::<SyncTask>();
+        let handle = Scheduler::<SyncTask>::new(receiver)
+            .control_socket(SOCKET_PATH)
+            .run();
+        assert!(Path::new(SOCKET_PATH).exists());
+
+        // A shutdown from outside of the socket stops listening without another client
+        handle.shutdown();
+        assert!(wait_until(|| !Path::new(SOCKET_PATH).exists()));
+        assert!(UnixStream::connect(SOCKET_PATH).is_err());
+    }
+
+    #[test]
+    fn control_socket_path() {
+        static SOCKET_PATH: &str = "/tmp/tulsa_control_path_test.sock";
+        let (_sender, receiver) = mpsc::channel::<SyncTask>();
+        let handle = Scheduler::<SyncTask>::new(receiver)
+            .control_socket(SOCKET_PATH)
+            .run();
+        let mode = fs::metadata(SOCKET_PATH).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        handle.shutdown();
+        assert!(wait_until(|| !Path::new(SOCKET_PATH).exists()));
+
+        // A file which is not a socket is not replaced
+        fs::write(SOCKET_PATH, "keep").unwrap();
+        let (_sender, receiver) = mpsc::channel::<SyncTask>();
+        let handle = Scheduler::<SyncTask>::new(receiver)
+            .control_socket(SOCKET_PATH)
+            .run();
+        assert_eq!(fs::read_to_string(SOCKET_PATH).unwrap(), "keep");
+        handle.shutdown();
+        fs::remove_file(SOCKET_PATH).unwrap();
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> SystemTime {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+            .into()
+    }
+
+    /// A one hour window which is open right now in `zone`.
+    fn current_hour(zone: Tz) -> ServiceWindow {
+        let hour = Utc::now().with_timezone(&zone).hour();
+        let (start, end) = (format!("{}:00", hour), format!("{}:00", hour + 1));
+        ServiceWindow::new(&start, &end).unwrap()
+    }
+
+    #[test]
+    fn service_window() {
+        assert_eq!(
+            ServiceWindow::new("05:00", "48:00"),
+            Err(WindowError::InvalidTime("48:00".to_string()))
+        );
+        assert!(ServiceWindow::new("5", "06:00").is_err());
+
+        // The window must be open at some point, and for no more than a day
+        for (start, end) in [("05:00", "05:00"), ("30:00", "05:00"), ("01:00", "26:00")] {
+            assert_eq!(
+                ServiceWindow::new(start, end),
+                Err(WindowError::InvalidRange(
+                    start.to_string(),
+                    end.to_string()
+                ))
+            );
+        }
+        assert!(ServiceWindow::new("30:00", "07:00").is_ok());
+
+        // Runs past midnight, so 00:30 on June 5th belongs to the service day of June 4th
+        let window = ServiceWindow::new("05:00", "01:30")
+            .unwrap()
+            .zone(Tz::America__New_York);
+        assert_eq!(
+            window,
+            ServiceWindow::new("05:00", "25:30")
+                .unwrap()
+                .zone(Tz::America__New_York)
+        );
+        assert!(window.is_open(utc(2024, 6, 5, 4, 30)));
+        assert!(!window.is_open(utc(2024, 6, 5, 6, 0)));
+        assert!(!window.is_open(utc(2024, 6, 5, 8, 59)));
+        assert!(window.is_open(utc(2024, 6, 5, 9, 0)));
+
+        // Clocks go forward at 02:00 on March 10th, and back at 02:00 on November 3rd. Service
+        // day times after the transition stay on the wall clock.
+        let window = ServiceWindow::new("05:00", "06:00")
+            .unwrap()
+            .zone(Tz::America__New_York);
+        assert!(window.is_open(utc(2024, 3, 10, 9, 30)));
+        assert!(!window.is_open(utc(2024, 3, 10, 10, 30)));
+        assert!(!window.is_open(utc(2024, 11, 3, 9, 30)));
+        assert!(window.is_open(utc(2024, 11, 3, 10, 30)));
+
+        // Before the transition, times are an hour off from the wall clock
+        let window = ServiceWindow::new("01:00", "04:00")
+            .unwrap()
+            .zone(Tz::America__New_York);
+        assert!(window.is_open(utc(2024, 3, 10, 5, 30)));
+        assert!(!window.is_open(utc(2024, 3, 10, 8, 0)));
+    }
+
+    #[test]
+    fn sync_scheduler_service_window() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver)
+            .time_zone(Tz::Asia__Tokyo)
+            .run();
+
+        // The first window uses the zone of the scheduler, and the second is closed in UTC
+        let open = current_hour(Tz::Asia__Tokyo);
+        let task = SyncTask::new(60, Duration::from_millis(100), || {}).window(open);
+        sender.send(task).unwrap();
+        let task = SyncTask::new(61, Duration::from_millis(100), || {}).window(open.zone(Tz::UTC));
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(handle.history(60).len(), 3);
+        assert!(handle.history(61).is_empty());
+    }
+
+    #[test]
+    fn async_scheduler_service_window() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        // The window is closed in UTC, so ticks are dropped, but a trigger still runs the task
+        let closed = current_hour(Tz::Asia__Tokyo);
+        let task = AsyncTask::recurring(62, Duration::from_millis(100), || async {}).window(closed);
+        sender.send(task).unwrap();
+        thread::sleep(Duration::from_millis(250));
+        assert!(handle.history(62).is_empty());
+
+        assert!(handle.trigger(62));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(handle.history(62).len(), 1);
+    }
+
+    #[test]
+    fn sync_scheduler_context() {
+        let (sender, receiver) = mpsc::channel();
+        Scheduler::<SyncTask>::new(receiver).run();
+
+        // The body keeps working until it is cancelled, then records what it saw
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = seen.clone();
+        let task =
+            SyncTask::with_context(70, Duration::from_millis(100), move |ctx: TaskContext| {
+                while !ctx.is_cancelled() && ctx.run() > 1 {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                s.lock()
+                    .unwrap()
+                    .push((ctx.id(), ctx.run(), ctx.scheduled_at()));
+            });
+        let start = SystemTime::now();
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(250));
+        sender.send(SyncTask::stop(70)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!((seen[0].0, seen[0].1), (70, 1));
+        assert_eq!((seen[1].0, seen[1].1), (70, 2));
+        let due = seen[1].2.duration_since(start).unwrap();
+        assert!(due >= Duration::from_millis(100) && due < Duration::from_millis(150));
+    }
+
+    #[test]
+    fn async_scheduler_grace_period() {
+        let (sender, receiver) = mpsc::channel();
+        Scheduler::<AsyncTask>::new(receiver)
+            .grace_period(Duration::from_millis(100))
+            .run();
+
+        // One body cleans up once it is cancelled, and the other is aborted after the grace period
+        let cleaned_up = Arc::new(AtomicUsize::new(0));
+        let c = cleaned_up.clone();
+        let task = AsyncTask::recurring_with_context(71, Duration::from_secs(10), move |ctx| {
+            let c = c.clone();
+            async move {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(10)) => {}
+                    _ = ctx.cancelled() => {}
+                }
+                c.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        sender.send(task).unwrap();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let f = finished.clone();
+        let task = AsyncTask::recurring(72, Duration::from_secs(10), move || {
+            let f = f.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(400)).await;
+                f.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+        sender.send(AsyncTask::stop(71)).unwrap();
+        sender.send(AsyncTask::stop(72)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(cleaned_up.load(Ordering::SeqCst), 1);
+
+        thread::sleep(Duration::from_millis(450));
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn file_lease() {
+        static LEASE_PATH: &str = "/tmp/tulsa_lease_test";
+        let _ = fs::remove_file(LEASE_PATH);
+        let mut a = FileLease::new(LEASE_PATH, Duration::from_millis(200)).holder("a");
+        let mut b = FileLease::new(LEASE_PATH, Duration::from_millis(200)).holder("b");
+
+        assert!(a.acquire().unwrap());
+        assert!(a.acquire().unwrap());
+        assert!(!b.acquire().unwrap());
+
+        // A released lease can be taken right away, and an expired one after its ttl
+        a.release().unwrap();
+        assert!(b.acquire().unwrap());
+        assert!(!a.acquire().unwrap());
+        thread::sleep(Duration::from_millis(250));
+        assert!(a.acquire().unwrap());
+    }
+
+    #[test]
+    fn lease_failover() {
+        static LEASE_PATH: &str = "/tmp/tulsa_lease_failover_test";
+        let _ = fs::remove_file(LEASE_PATH);
+
+        let start = |holder: &str| {
+            let (sender, receiver) = mpsc::channel();
+            let lease = FileLease::new(LEASE_PATH, Duration::from_millis(150)).holder(holder);
+            let handle = Scheduler::<SyncTask>::new(receiver).lease(lease).run();
+            sender
+                .send(SyncTask::new(80, Duration::from_millis(50), || {}))
+                .unwrap();
+            (sender, handle)
+        };
+        let (_a_sender, a) = start("a");
+        thread::sleep(Duration::from_millis(20));
+        let (_b_sender, b) = start("b");
+
+        // Only the first instance runs the task
+        thread::sleep(Duration::from_millis(200));
+        assert!(a.is_leader());
+        assert!(!b.is_leader());
+        assert!(!a.history(80).is_empty());
+        assert!(b.history(80).is_empty());
+
+        // Shutting down releases the lease, which the second instance picks up
+        a.shutdown();
+        thread::sleep(Duration::from_millis(250));
+        assert!(b.is_leader());
+        assert!(!b.history(80).is_empty());
+    }
+
+    #[test]
+    fn async_once_lease() {
+        static LEASE_PATH: &str = "/tmp/tulsa_lease_once_test";
+        let _ = fs::remove_file(LEASE_PATH);
+
+        // Another instance holds the lease, without having to renew it during the test
+        let mut other = FileLease::new(LEASE_PATH, Duration::from_secs(10)).holder("a");
+        assert!(other.acquire().unwrap());
+
+        let (sender, receiver) = mpsc::channel();
+        let lease = FileLease::new(LEASE_PATH, Duration::from_millis(150)).holder("b");
+        let handle = Scheduler::<AsyncTask>::new(receiver).lease(lease).run();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let r = runs.clone();
+        sender
+            .send(AsyncTask::new(90, async move {
+                r.fetch_add(1, Ordering::SeqCst);
+            }))
+            .unwrap();
+        let r = runs.clone();
+        let graph = AsyncGraph::new().task(1, move || {
+            r.fetch_add(1, Ordering::SeqCst);
+            async {}
+        });
+        sender
+            .send(AsyncTask::graph_once(91, graph).unwrap())
+            .unwrap();
+
+        // Neither one-shot task runs without the lease
+        thread::sleep(Duration::from_millis(200));
+        assert!(!handle.is_leader());
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        // They run once the lease is released and taken over
+        other.release().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(handle.is_leader());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(handle.history(90).len(), 1);
+        handle.shutdown();
+    }
+
+    #[test]
+    fn sync_once_lease() {
+        static LEASE_PATH: &str = "/tmp/tulsa_lease_sync_once_test";
+        let _ = fs::remove_file(LEASE_PATH);
+
+        let mut other = FileLease::new(LEASE_PATH, Duration::from_secs(10)).holder("a");
+        assert!(other.acquire().unwrap());
+
+        let (sender, receiver) = mpsc::channel();
+        let lease = FileLease::new(LEASE_PATH, Duration::from_millis(150)).holder("b");
+        let handle = Scheduler::<SyncTask>::new(receiver).lease(lease).run();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let r = runs.clone();
+        let graph = SyncGraph::new().task(1, move || {
+            r.fetch_add(1, Ordering::SeqCst);
+        });
+        sender
+            .send(SyncTask::graph_once(92, graph).unwrap())
+            .unwrap();
+
+        // The graph waits for the lease, and then for being resumed
+        assert!(wait_until(|| handle.tasks().len() == 1));
+        assert!(handle.pause(92));
+        other.release().unwrap();
+        assert!(wait_until(|| handle.is_leader()));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        assert!(handle.resume(92));
+        assert!(wait_until(|| runs.load(Ordering::SeqCst) == 1));
+        handle.shutdown();
+    }
+
+    #[test]
+    fn metrics() {
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let limits = Limits {
+            min_frequency: Duration::from_millis(100),
+            ..Limits::default()
+        };
+        let (sender, receiver) = channel::<SyncTask>(limits);
+        let sender = sender.metrics(metrics.clone());
+        Scheduler::<SyncTask>::new(receiver)
+            .metrics(metrics.clone())
+            .run();
+
+        let fail = || Err::<(), _>("oops");
+        let slow = || thread::sleep(Duration::from_millis(130));
+        sender
+            .send(SyncTask::new(90, Duration::from_millis(100), fail))
+            .unwrap();
+        sender
+            .send(SyncTask::new(91, Duration::from_millis(100), boom))
+            .unwrap();
+        let task = SyncTask::new(92, Duration::from_millis(100), slow).overlap(Overlap::Skip);
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(250));
+        let text = metrics.render();
+        assert!(text.contains("tulsa_registered_tasks 3\n"));
+        assert!(text.contains("tulsa_queue_depth 0\n"));
+        assert!(text.contains("tulsa_executions_total{task=\"90\",outcome=\"failure\"} 3\n"));
+        assert!(text.contains("tulsa_executions_total{task=\"91\",outcome=\"panic\"} 3\n"));
+        assert!(text.contains("tulsa_skipped_ticks_total{task=\"92\"} 1\n"));
+        assert!(
+            text.contains("tulsa_execution_duration_seconds_bucket{task=\"92\",le=\"0.1\"} 0\n")
+        );
+        assert!(text.contains("tulsa_execution_duration_seconds_count{task=\"92\"} 1\n"));
+        assert!(text.contains("tulsa_schedule_lag_seconds_count{task=\"90\"} 3\n"));
+        assert!(text.contains("# TYPE tulsa_schedule_lag_seconds histogram\n"));
+    }
+
+    /// A span which was created: its id, name, fields and the id of its parent.
+    type RecordedSpan = (u64, String, String, Option<u64>);
+
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Vec<RecordedSpan>>>);
+
+    struct FieldVisitor(String);
+
+    impl Visit for FieldVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0 += &format!("{}={:?} ", field.name(), value);
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0 += &format!("{}={} ", field.name(), value);
+        }
+    }
+
+    impl<S> Layer<S> for SpanRecorder
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut fields = FieldVisitor(String::new());
+            attrs.record(&mut fields);
+            let parent = ctx
+                .span(id)
+                .and_then(|span| span.parent())
+                .map(|parent| parent.id().into_u64());
+            self.0.lock().unwrap().push((
+                id.into_u64(),
+                attrs.metadata().name().to_string(),
+                fields.0,
+                parent,
+            ));
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let recorder = SpanRecorder::default();
+        tracing_subscriber::registry().with(recorder.clone()).init();
+
+        let (sender, receiver) = mpsc::sync_channel(1000);
+        Scheduler::<SyncTask>::new(receiver).run();
+
+        let request = info_span!("request");
+        let task = SyncTask::new(100, Duration::from_millis(100), || {})
+            .tag("feed", "buses")
+            .parent_span(request.clone());
+        sender.send(task).unwrap();
+        thread::sleep(Duration::from_millis(150));
+
+        let spans = recorder.0.lock().unwrap().clone();
+        let find = |name: &str, parent: Option<u64>| {
+            spans
+                .iter()
+                .find(|(_, n, _, p)| n == name && *p == parent)
+                .cloned()
+        };
+        let (task_id, _, fields, _) =
+            find("task", request.id().map(|id| id.into_u64())).expect("no task span");
+        assert!(fields.contains("id=100 "));
+        assert!(fields.contains("frequency=Some(100ms) "));
+        assert!(fields.contains("tags=feed=buses "));
+        let (_, _, fields, _) = find("execution", Some(task_id)).expect("no execution span");
+        assert!(fields.contains("run=1 "));
+        assert!(fields.contains("kind=Tick "));
+    }
+
+    #[test]
+    fn snapshot() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).run();
+
+        let spec = serde_json::json!({ "feed": "buses" });
+        let start = SystemTime::now();
+        let task = SyncTask::new(110, Duration::from_millis(300), || {}).describe(spec.clone());
+        sender.send(task).unwrap();
+        sender
+            .send(SyncTask::new(111, Duration::from_millis(300), || {}))
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        handle.pause(111);
+
+        // The snapshot survives a round trip through JSON
+        let json = serde_json::to_string(&handle.snapshot()).unwrap();
+        handle.shutdown();
+        let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.tasks.len(), 2);
+        let task = &snapshot.tasks[0];
+        assert_eq!(task.id, 110);
+        assert_eq!(task.spec, Some(spec));
+        assert_eq!(task.frequency, Some(Duration::from_millis(300)));
+        assert_eq!((task.runs, task.paused), (1, false));
+        let due = task.next_tick.unwrap().duration_since(start).unwrap();
+        assert!(due >= Duration::from_millis(290) && due < Duration::from_millis(350));
+        assert!(snapshot.tasks[1].paused);
+
+        // The new scheduler waits for the tick which was due, and keeps counting the runs
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<SyncTask>::new(receiver).restore(snapshot).run();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = seen.clone();
+        let task =
+            SyncTask::with_context(110, Duration::from_millis(300), move |ctx: TaskContext| {
+                s.lock().unwrap().push((ctx.run(), SystemTime::now()));
+            });
+        sender.send(task).unwrap();
+        sender
+            .send(SyncTask::new(111, Duration::from_millis(300), || {}))
+            .unwrap();
+        thread::sleep(Duration::from_millis(300));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].0, 2);
+        assert!(seen[0].1.duration_since(start).unwrap() >= Duration::from_millis(290));
+        assert!(handle.tasks()[1].paused);
+    }
+
+    #[test]
+    fn adaptive_restore() {
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver).run();
+
+        // The task backs off to its upper bound after its first run
+        let task = AsyncTask::recurring(113, Duration::from_millis(50), || async {
+            Duration::from_millis(400)
+        })
+        .adaptive(Duration::from_millis(50), Duration::from_millis(400))
+        .unwrap();
+        sender.send(task).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let snapshot = handle.snapshot();
+        handle.shutdown();
+        assert_eq!(
+            snapshot.tasks[0].frequency,
+            Some(Duration::from_millis(400))
+        );
+
+        // Bodies which no longer suggest a delay show which frequency the tasks resumed with
+        let seen = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+        let async_handle = Scheduler::<AsyncTask>::new(receiver)
+            .restore(snapshot.clone())
+            .run();
+        let s = seen.clone();
+        let task = AsyncTask::recurring(113, Duration::from_millis(50), move || {
+            s.fetch_add(1, Ordering::SeqCst);
+            async {}
+        })
+        .adaptive(Duration::from_millis(50), Duration::from_millis(400))
+        .unwrap();
+        sender.send(task).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let sync_handle = Scheduler::<SyncTask>::new(receiver).restore(snapshot).run();
+        let s = seen.clone();
+        let task = SyncTask::new(113, Duration::from_millis(50), move || {
+            s.fetch_add(1, Ordering::SeqCst);
+        })
+        .adaptive(Duration::from_millis(50), Duration::from_millis(400))
+        .unwrap();
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(
+            async_handle.tasks()[0].frequency,
+            Some(Duration::from_millis(400))
+        );
+        assert_eq!(
+            sync_handle.tasks()[0].frequency,
+            Some(Duration::from_millis(400))
+        );
+        // Each task ran once at the tick which was due, where 50ms would have run it many times
+        assert_eq!(seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn async_scheduler_restore() {
+        let snapshot = Snapshot {
+            taken_at: SystemTime::now(),
+            tasks: vec![TaskSnapshot {
+                id: 112,
+                spec: None,
+                frequency: Some(Duration::from_secs(1)),
+                next_tick: Some(SystemTime::now() + Duration::from_millis(200)),
+                paused: false,
+                runs: 5,
+                skipped: 2,
+            }],
+        };
+        let (sender, receiver) = mpsc::channel();
+        let handle = Scheduler::<AsyncTask>::new(receiver)
+            .restore(snapshot)
+            .run();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = seen.clone();
+        let task = AsyncTask::recurring_with_context(112, Duration::from_secs(1), move |ctx| {
+            s.lock().unwrap().push(ctx.run());
+            async {}
+        });
+        sender.send(task).unwrap();
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(seen.lock().unwrap().is_empty());
+        assert_eq!(handle.tasks()[0].skipped, 2);
+
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(*seen.lock().unwrap(), vec![6]);
+        let next_tick = handle.snapshot().tasks[0].next_tick.unwrap();
+        assert!(next_tick > SystemTime::now() + Duration::from_millis(500));
+    }
 }